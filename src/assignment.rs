@@ -0,0 +1,196 @@
+// Solves the rectangular assignment problem with the Hungarian algorithm.
+//
+// costs[r][c] is the cost of assigning row r to column c. Every row must have
+// the same number of columns. Returns, for each row, the column it was
+// assigned to. If there are more rows than columns some rows will be left
+// unassigned. The total cost of the assignment is minimal.
+//
+// Costs must be finite. Callers that want to forbid an assignment should use a
+// large cost and discard the pairing afterwards.
+pub fn hungarian(costs: &[Vec<f64>]) -> Vec<Option<usize>> {
+    let rows = costs.len();
+    let cols = costs.first().map_or(0, |r| r.len());
+    if rows == 0 || cols == 0 {
+        return vec![None; rows];
+    }
+    if rows > cols {
+        // The algorithm below needs at least as many columns as rows, so
+        // solve the transposed problem and invert the result.
+        let transposed: Vec<Vec<f64>> = (0..cols)
+            .map(|c| (0..rows).map(|r| costs[r][c]).collect())
+            .collect();
+        let mut assignment = vec![None; rows];
+        for (c, r) in hungarian(&transposed).into_iter().enumerate() {
+            if let Some(r) = r {
+                assignment[r] = Some(c);
+            }
+        }
+        return assignment;
+    }
+
+    // The classic O(n^2 m) formulation with row and column potentials. Index
+    // 0 is a sentinel, so rows and columns are 1-based below.
+    let mut u = vec![0.; rows + 1];
+    let mut v = vec![0.; cols + 1];
+    // The row assigned to each column, 0 if none.
+    let mut matched_row = vec![0; cols + 1];
+    let mut way = vec![0; cols + 1];
+    for r in 1..=rows {
+        matched_row[0] = r;
+        let mut c0 = 0;
+        let mut min_slack = vec![f64::INFINITY; cols + 1];
+        let mut used = vec![false; cols + 1];
+        loop {
+            used[c0] = true;
+            let r0 = matched_row[c0];
+            let mut delta = f64::INFINITY;
+            let mut c1 = 0;
+            for c in 1..=cols {
+                if used[c] {
+                    continue;
+                }
+                let slack = costs[r0 - 1][c - 1] - u[r0] - v[c];
+                if slack < min_slack[c] {
+                    min_slack[c] = slack;
+                    way[c] = c0;
+                }
+                if min_slack[c] < delta {
+                    delta = min_slack[c];
+                    c1 = c;
+                }
+            }
+            for c in 0..=cols {
+                if used[c] {
+                    u[matched_row[c]] += delta;
+                    v[c] -= delta;
+                } else {
+                    min_slack[c] -= delta;
+                }
+            }
+            c0 = c1;
+            if matched_row[c0] == 0 {
+                break;
+            }
+        }
+        // Walk the augmenting path back to the start.
+        loop {
+            let c1 = way[c0];
+            matched_row[c0] = matched_row[c1];
+            c0 = c1;
+            if c0 == 0 {
+                break;
+            }
+        }
+    }
+
+    let mut assignment = vec![None; rows];
+    for c in 1..=cols {
+        if matched_row[c] != 0 {
+            assignment[matched_row[c] - 1] = Some(c - 1);
+        }
+    }
+    assignment
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn total(costs: &[Vec<f64>], assignment: &[Option<usize>]) -> f64 {
+        assignment
+            .iter()
+            .enumerate()
+            .filter_map(|(r, c)| c.map(|c| costs[r][c]))
+            .sum()
+    }
+
+    // The cheapest total over every way of assigning min(rows, cols) pairs.
+    fn brute_force(costs: &[Vec<f64>], r: usize, used: &mut Vec<bool>, pairs: usize) -> f64 {
+        if pairs == 0 {
+            return 0.;
+        }
+        if r == costs.len() {
+            return f64::INFINITY;
+        }
+        // Leave this row out, if there are rows to spare.
+        let mut best = if costs.len() - r > pairs {
+            brute_force(costs, r + 1, used, pairs)
+        } else {
+            f64::INFINITY
+        };
+        for c in 0..used.len() {
+            if !used[c] {
+                used[c] = true;
+                best = best.min(costs[r][c] + brute_force(costs, r + 1, used, pairs - 1));
+                used[c] = false;
+            }
+        }
+        best
+    }
+
+    #[test]
+    fn square_known_answers() {
+        let costs = vec![vec![4., 1., 3.], vec![2., 0., 5.], vec![3., 2., 2.]];
+        assert_eq!(hungarian(&costs), vec![Some(1), Some(0), Some(2)]);
+        let costs = vec![
+            vec![9., 2., 7., 8.],
+            vec![6., 4., 3., 7.],
+            vec![5., 8., 1., 8.],
+            vec![7., 6., 9., 4.],
+        ];
+        let assignment = hungarian(&costs);
+        assert_eq!(assignment, vec![Some(1), Some(0), Some(2), Some(3)]);
+        assert_eq!(total(&costs, &assignment), 13.);
+    }
+
+    #[test]
+    fn rectangular_leaves_extra_rows_or_columns_unassigned() {
+        let wide = vec![vec![10., 1., 10.], vec![1., 10., 10.]];
+        assert_eq!(hungarian(&wide), vec![Some(1), Some(0)]);
+        let tall = vec![vec![1., 10.], vec![10., 1.], vec![5., 5.]];
+        assert_eq!(hungarian(&tall), vec![Some(0), Some(1), None]);
+        assert_eq!(hungarian(&[]), Vec::new());
+        assert_eq!(hungarian(&[vec![], vec![]]), vec![None, None]);
+    }
+
+    #[test]
+    fn forbidden_pairing_only_when_unavoidable() {
+        // Both rows can only really go to column 1. One of them has to take
+        // the forbidden column 0, and it should be the one that loses least
+        // by it.
+        const FORBIDDEN: f64 = 1e6;
+        let costs = vec![vec![FORBIDDEN, 1.], vec![FORBIDDEN, 2.]];
+        let assignment = hungarian(&costs);
+        assert_eq!(assignment, vec![Some(1), Some(0)]);
+        assert_eq!(total(&costs, &assignment), FORBIDDEN + 1.);
+        // With a spare column nobody needs to.
+        let costs = vec![vec![FORBIDDEN, 1., 3.], vec![FORBIDDEN, 2., 4.]];
+        assert_eq!(hungarian(&costs), vec![Some(1), Some(2)]);
+    }
+
+    #[test]
+    fn matches_brute_force() {
+        // A simple deterministic generator, so failures are reproducible.
+        let mut seed = 12345u64;
+        let mut next = move || {
+            seed = seed.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+            (seed >> 33) as f64 / (1u64 << 31) as f64 * 100.
+        };
+        for (rows, cols) in [(1, 1), (3, 3), (4, 2), (2, 5), (5, 5), (5, 4)] {
+            for _ in 0..20 {
+                let costs: Vec<Vec<f64>> = (0..rows)
+                    .map(|_| (0..cols).map(|_| next()).collect())
+                    .collect();
+                let assignment = hungarian(&costs);
+                // Each column is used at most once.
+                let mut cols_used: Vec<usize> = assignment.iter().flatten().copied().collect();
+                cols_used.sort();
+                cols_used.dedup();
+                assert_eq!(cols_used.len(), rows.min(cols));
+                let best = brute_force(&costs, 0, &mut vec![false; cols], rows.min(cols));
+                let cost = total(&costs, &assignment);
+                assert!((cost - best).abs() < 1e-9, "{:?} {} {}", costs, cost, best);
+            }
+        }
+    }
+}
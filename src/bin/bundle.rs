@@ -0,0 +1,300 @@
+// Bundles one of the crate's modules and everything it uses into a single
+// source file that can be uploaded to the game.
+//
+// Usage: cargo run --bin bundle -- <entry module> [output file]
+//
+// The entry module (e.g. tutorial_search, or lib for the whole crate) is
+// emitted at the top level of the bundle so that its Ship is what the game
+// sees. Every module it reaches through a crate:: path, directly or through
+// other modules, is inlined after it as a `pub mod name { ... }` block. Since
+// those blocks sit at the root of the bundle, crate:: paths inside them keep
+// resolving to the same modules as they do in the crate. If the entry itself
+// declares modules with `mod name;`, the declarations are replaced with the
+// modules' contents.
+//
+// The bundle is written to the output file if one is given, or to stdout.
+use std::{
+    collections::BTreeSet,
+    env, fs,
+    path::{Path, PathBuf},
+    process::ExitCode,
+};
+
+fn main() -> ExitCode {
+    let args: Vec<String> = env::args().skip(1).collect();
+    if args.is_empty() || args.len() > 2 {
+        eprintln!("usage: bundle <entry module> [output file]");
+        return ExitCode::from(2);
+    }
+    let src_dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("src");
+    let bundle = match bundle(&src_dir, &args[0]) {
+        Ok(bundle) => bundle,
+        Err(e) => {
+            eprintln!("bundle: {e}");
+            return ExitCode::FAILURE;
+        }
+    };
+    match args.get(1) {
+        Some(path) => {
+            if let Err(e) = fs::write(path, bundle) {
+                eprintln!("bundle: couldn't write {path}: {e}");
+                return ExitCode::FAILURE;
+            }
+        }
+        None => print!("{bundle}"),
+    }
+    ExitCode::SUCCESS
+}
+
+fn bundle(src_dir: &Path, entry: &str) -> Result<String, String> {
+    let mut inlined = BTreeSet::new();
+    inlined.insert(entry.to_string());
+    let entry_source =
+        inline_mod_declarations(src_dir, &read_module(src_dir, entry)?, &mut inlined)?;
+
+    let mut out = format!("// Generated by `cargo run --bin bundle -- {entry}`. Do not edit.\n\n");
+    out += &entry_source;
+
+    // Collect the crate:: dependencies transitively. Modules the entry declared
+    // itself have already been inlined in place.
+    let mut queue: Vec<String> = crate_dependencies(&entry_source)
+        .into_iter()
+        .filter(|m| !inlined.contains(m))
+        .collect();
+    let mut modules = BTreeSet::new();
+    while let Some(module) = queue.pop() {
+        if !modules.insert(module.clone()) {
+            continue;
+        }
+        let source = read_module(src_dir, &module)?;
+        if let Some(decl) = mod_declarations(&source).into_iter().next() {
+            return Err(format!(
+                "{module}.rs declares `mod {decl};`, which is only supported in the entry module"
+            ));
+        }
+        queue.extend(
+            crate_dependencies(&source)
+                .into_iter()
+                .filter(|m| !inlined.contains(m) && !modules.contains(m)),
+        );
+    }
+    for module in modules {
+        out += &format!(
+            "\npub mod {module} {{\n{}}}\n",
+            read_module(src_dir, &module)?
+        );
+    }
+    Ok(out)
+}
+
+fn module_path(src_dir: &Path, module: &str) -> PathBuf {
+    src_dir.join(format!("{module}.rs"))
+}
+
+fn read_module(src_dir: &Path, module: &str) -> Result<String, String> {
+    let path = module_path(src_dir, module);
+    fs::read_to_string(&path).map_err(|e| format!("couldn't read {}: {e}", path.display()))
+}
+
+// Replaces every `mod name;` declaration in source with the module's contents.
+fn inline_mod_declarations(
+    src_dir: &Path,
+    source: &str,
+    inlined: &mut BTreeSet<String>,
+) -> Result<String, String> {
+    let mut out = String::new();
+    for line in source.lines() {
+        match parse_mod_declaration(line) {
+            Some((prefix, name)) => {
+                let contents = read_module(src_dir, name)?;
+                out += &format!("{prefix}mod {name} {{\n{contents}}}\n");
+                inlined.insert(name.to_string());
+            }
+            None => {
+                out += line;
+                out.push('\n');
+            }
+        }
+    }
+    Ok(out)
+}
+
+fn mod_declarations(source: &str) -> Vec<&str> {
+    source
+        .lines()
+        .filter_map(parse_mod_declaration)
+        .map(|(_, name)| name)
+        .collect()
+}
+
+// Parses a line like `pub mod name;` into its visibility prefix ("pub ") and
+// the module name.
+fn parse_mod_declaration(line: &str) -> Option<(&str, &str)> {
+    let line = line.trim();
+    let (prefix, rest) = line.split_at(line.find("mod ")?);
+    if !prefix.is_empty() && !prefix.starts_with("pub") {
+        return None;
+    }
+    let name = rest.strip_prefix("mod ")?.strip_suffix(';')?.trim();
+    is_identifier(name).then_some((prefix, name))
+}
+
+// Returns the names of the modules that source reaches through crate:: paths,
+// including those inside use groups such as `use crate::{a::*, b::C}`. Paths
+// in #[cfg(test)] items don't count, since the game never builds them.
+fn crate_dependencies(source: &str) -> BTreeSet<String> {
+    let code: String = source
+        .lines()
+        .map(|line| line.split("//").next().unwrap())
+        .collect::<Vec<_>>()
+        .join("\n");
+    let code = strip_cfg_test(&code);
+    let mut deps = BTreeSet::new();
+    let mut rest = code.as_str();
+    while let Some(i) = rest.find("crate::") {
+        rest = &rest[i + "crate::".len()..];
+        if let Some(group) = rest.strip_prefix('{') {
+            let group = &group[..matching_brace(group).unwrap_or(group.len())];
+            deps.extend(split_top_level(group).map(|p| leading_identifier(p).to_string()));
+        } else {
+            deps.insert(leading_identifier(rest).to_string());
+        }
+    }
+    deps.retain(|d| !d.is_empty() && d != "self");
+    deps
+}
+
+// Removes each item marked #[cfg(test)], up to the `;` or the closing brace
+// that ends it.
+fn strip_cfg_test(code: &str) -> String {
+    const ATTRIBUTE: &str = "#[cfg(test)]";
+    let mut out = String::new();
+    let mut rest = code;
+    while let Some(i) = rest.find(ATTRIBUTE) {
+        out += &rest[..i];
+        rest = &rest[i + ATTRIBUTE.len()..];
+        let Some(end) = rest.find(['{', ';']) else {
+            return out;
+        };
+        rest = if rest[end..].starts_with(';') {
+            &rest[end + 1..]
+        } else {
+            match matching_brace(&rest[end + 1..]) {
+                Some(close) => &rest[end + 1 + close + 1..],
+                None => return out,
+            }
+        };
+    }
+    out + rest
+}
+
+// The index of the brace closing a group that starts just before s.
+fn matching_brace(s: &str) -> Option<usize> {
+    let mut depth = 0;
+    for (i, c) in s.char_indices() {
+        match c {
+            '{' => depth += 1,
+            '}' if depth == 0 => return Some(i),
+            '}' => depth -= 1,
+            _ => {}
+        }
+    }
+    None
+}
+
+// Splits a use group on the commas that aren't inside a nested group.
+fn split_top_level(group: &str) -> impl Iterator<Item = &str> {
+    let mut parts = Vec::new();
+    let mut depth = 0;
+    let mut start = 0;
+    for (i, c) in group.char_indices() {
+        match c {
+            '{' => depth += 1,
+            '}' => depth -= 1,
+            ',' if depth == 0 => {
+                parts.push(&group[start..i]);
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    parts.push(&group[start..]);
+    parts.into_iter().map(str::trim)
+}
+
+fn leading_identifier(s: &str) -> &str {
+    let end = s
+        .find(|c: char| !(c.is_alphanumeric() || c == '_'))
+        .unwrap_or(s.len());
+    &s[..end]
+}
+
+fn is_identifier(s: &str) -> bool {
+    !s.is_empty() && leading_identifier(s) == s
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn src_dir() -> PathBuf {
+        Path::new(env!("CARGO_MANIFEST_DIR")).join("src")
+    }
+
+    #[test]
+    fn finds_crate_dependencies() {
+        let source = "use crate::{\n    contacts::*,\n    control::{Helm, lead},\n};\n\
+                      use crate::radar::Radar;\n\
+                      // crate::commented_out\n\
+                      fn f() { crate::matrix::Matrix::<2, 2>::zeros(); }\n";
+        let deps: Vec<_> = crate_dependencies(source).into_iter().collect();
+        assert_eq!(deps, ["contacts", "control", "matrix", "radar"]);
+    }
+
+    #[test]
+    fn ignores_cfg_test_items() {
+        let source = "use crate::radar::Radar;\n\
+                      #[cfg(test)]\nmod test_env;\n\
+                      #[cfg(test)]\nmod tests {\n    use crate::sim::Sim;\n    \
+                      fn f() { crate::test_env::Env::new(); }\n}\n\
+                      fn g() { crate::matrix::Matrix::<2, 2>::zeros(); }\n";
+        let deps: Vec<_> = crate_dependencies(source).into_iter().collect();
+        assert_eq!(deps, ["matrix", "radar"]);
+    }
+
+    #[test]
+    fn parses_mod_declarations() {
+        assert_eq!(
+            parse_mod_declaration("pub mod radar;"),
+            Some(("pub ", "radar"))
+        );
+        assert_eq!(
+            parse_mod_declaration("mod test_env;"),
+            Some(("", "test_env"))
+        );
+        assert_eq!(parse_mod_declaration("mod tests {"), None);
+        assert_eq!(parse_mod_declaration("// this mod is;"), None);
+    }
+
+    #[test]
+    fn bundles_transitive_dependencies() {
+        let bundle = bundle(&src_dir(), "tutorial_search").unwrap();
+        assert!(bundle.contains("pub struct Ship"));
+        for module in ["assignment", "contacts", "control", "matrix", "radar"] {
+            assert!(
+                bundle.contains(&format!("\npub mod {module} {{\n")),
+                "{module}"
+            );
+        }
+        assert!(!bundle.contains("pub mod tutorial_lead"));
+        // Only tests use these.
+        for module in ["test_env", "sim"] {
+            assert!(!bundle.contains(&format!("pub mod {module} ")), "{module}");
+        }
+    }
+
+    #[test]
+    fn missing_entry_is_an_error() {
+        assert!(bundle(&src_dir(), "no_such_module").is_err());
+    }
+}
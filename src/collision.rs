@@ -0,0 +1,426 @@
+// Keeping clear of other ships and asteroids.
+//
+// We use velocity obstacles. For each obstacle, the velocities of ours that
+// would bring us within a safe separation of it in the next HORIZON seconds
+// form a cone in velocity space, cut off at the end nearest us. Each tick we
+// take the velocity the acceleration already commanded would give us next
+// tick and, if it falls inside any obstacle's cone, replace it with the
+// closest velocity that doesn't, and accelerate toward that instead. So
+// avoidance should run after whatever decides where we're going.
+//
+// Obstacles are the contacts we're tracking and the friendly ships that
+// report their positions over the radio. Every ship running the avoidance
+// makes such reports itself.
+use crate::{
+    contacts::Contacts, control::commanded_acceleration, flight::match_velocity, radar::ship_dim,
+};
+use oort_api::prelude::*;
+use std::collections::HashMap;
+
+// How far ahead we look for collisions.
+const HORIZON: f64 = 10.;
+
+// Something to keep clear of.
+#[derive(Clone, Copy, Debug)]
+pub struct Obstacle {
+    pub pos: Vec2,
+    pub vel: Vec2,
+    pub radius: f64,
+}
+
+// How long until a body of the given radius at pos, moving at vel, comes
+// within contact of the obstacle, or None if it never does. Zero if they are
+// already in contact and closing.
+pub fn time_to_collision(pos: Vec2, vel: Vec2, radius: f64, obstacle: &Obstacle) -> Option<f64> {
+    // Solve |p - w t| = r for the obstacle's position p relative to us and
+    // our velocity w relative to it.
+    let p = obstacle.pos - pos;
+    let w = vel - obstacle.vel;
+    let r = radius + obstacle.radius;
+    if p.dot(p) <= r * r {
+        return (w.dot(p) > 0.).then_some(0.);
+    }
+    let (a, b, c) = (w.dot(w), -2. * w.dot(p), p.dot(p) - r * r);
+    let disc = b * b - 4. * a * c;
+    if a == 0. || disc < 0. {
+        return None;
+    }
+    let t = (-b - disc.sqrt()) / (2. * a);
+    (t >= 0.).then_some(t)
+}
+
+// Returns the velocity closest to preferred with which a body of the given
+// radius at pos won't hit any of the obstacles within HORIZON seconds. If
+// there's no such velocity, returns the one that puts off the first
+// collision longest.
+pub fn avoid(pos: Vec2, preferred: Vec2, radius: f64, obstacles: &[Obstacle]) -> Vec2 {
+    let first_collision = |vel: Vec2| {
+        obstacles
+            .iter()
+            .filter_map(|o| time_to_collision(pos, vel, radius, o))
+            .fold(f64::INFINITY, f64::min)
+    };
+    if first_collision(preferred) >= HORIZON {
+        return preferred;
+    }
+
+    // The closest safe velocity lies on the edge of one of the cones, so
+    // try the closest point on each edge of each cone. They're pushed out a
+    // little so that rounding doesn't leave them just inside.
+    let mut candidates = Vec::new();
+    for o in obstacles {
+        let p = o.pos - pos;
+        let r = radius + o.radius;
+        let w = preferred - o.vel;
+        let distance = p.length();
+        if distance <= r {
+            // We're too close for there to be a cone: just back away.
+            let away = if distance > 0. {
+                -p / distance
+            } else {
+                vec2(1., 0.)
+            };
+            candidates.push(o.vel + away * w.length().max(1.));
+            continue;
+        }
+        // The sides of the cone.
+        let half_angle = (r / distance).asin() * 1.01;
+        for side in [-half_angle, half_angle] {
+            let edge = p.normalize().rotate(side);
+            candidates.push(o.vel + edge * w.dot(edge).max(0.));
+        }
+        // The end nearest us: velocities that would reach the obstacle just
+        // as the horizon runs out.
+        let center = p / HORIZON;
+        let offset = w - center;
+        let dir = if offset.length() > 0. {
+            offset.normalize()
+        } else {
+            -p.normalize()
+        };
+        candidates.push(o.vel + center + dir * r / HORIZON * 1.01);
+    }
+
+    let safe = candidates
+        .iter()
+        .filter(|&&v| first_collision(v) >= HORIZON)
+        .min_by(|a, b| {
+            (**a - preferred)
+                .length()
+                .total_cmp(&(**b - preferred).length())
+        });
+    match safe {
+        Some(&v) => v,
+        None => candidates
+            .into_iter()
+            .max_by(|&a, &b| first_collision(a).total_cmp(&first_collision(b)))
+            .unwrap_or(preferred),
+    }
+}
+
+// The radio our position reports go out on, and the channel it's tuned to.
+const RADIO: usize = 7;
+const CHANNEL: usize = 7;
+
+// Marks a radio message as a position report.
+const REPORT_TAG: u8 = 0xa5;
+
+// Reports older than this are forgotten.
+const REPORT_TIMEOUT: f64 = 1.;
+
+// A friendly ship's report of where it is.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Report {
+    pub id: u32,
+    pub class: Class,
+    pub pos: Vec2,
+    pub vel: Vec2,
+}
+
+impl Report {
+    pub fn encode(&self) -> [u8; 32] {
+        let mut bytes = [0; 32];
+        bytes[0] = REPORT_TAG;
+        bytes[1..5].copy_from_slice(&self.id.to_le_bytes());
+        bytes[5] = self.class as u8;
+        let values = [self.pos.x, self.pos.y, self.vel.x, self.vel.y];
+        for (i, v) in values.iter().enumerate() {
+            let start = 6 + 4 * i;
+            bytes[start..start + 4].copy_from_slice(&(*v as f32).to_le_bytes());
+        }
+        bytes
+    }
+
+    // Returns None if the message isn't a position report.
+    pub fn decode(bytes: &[u8; 32]) -> Option<Report> {
+        if bytes[0] != REPORT_TAG {
+            return None;
+        }
+        let value = |i: usize| {
+            let start = 6 + 4 * i;
+            f32::from_le_bytes(bytes[start..start + 4].try_into().unwrap()) as f64
+        };
+        Some(Report {
+            id: u32::from_le_bytes(bytes[1..5].try_into().unwrap()),
+            class: Class::from_f64(bytes[5] as f64),
+            pos: vec2(value(0), value(1)),
+            vel: vec2(value(2), value(3)),
+        })
+    }
+}
+
+// The friendly ships we've heard from recently.
+pub struct Friendlies {
+    // The latest report from each ship, by id, and when it was sent.
+    reports: HashMap<u32, (Report, f64)>,
+}
+
+impl Default for Friendlies {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Friendlies {
+    pub fn new() -> Friendlies {
+        Friendlies {
+            reports: HashMap::new(),
+        }
+    }
+
+    // Listens for reports from other ships and sends our own. The radio
+    // selected before the call is selected again afterwards.
+    pub fn tick(&mut self) {
+        let now = current_time();
+        let previous_radio =
+            oort_api::sys::read_system_state(oort_api::SystemState::SelectedRadio) as usize;
+        select_radio(RADIO);
+        set_radio_channel(CHANNEL);
+        if let Some(report) = receive_bytes().as_ref().and_then(Report::decode)
+            && report.id != id()
+        {
+            // Messages arrive the tick after they're sent.
+            self.reports.insert(report.id, (report, now - TICK_LENGTH));
+        }
+        self.reports.retain(|_, (_, t)| now - *t < REPORT_TIMEOUT);
+        send_bytes(
+            &Report {
+                id: id(),
+                class: class(),
+                pos: position(),
+                vel: velocity(),
+            }
+            .encode(),
+        );
+        select_radio(previous_radio);
+    }
+
+    // Where each friendly ship is now, extrapolated from its last report.
+    pub fn obstacles(&self) -> impl Iterator<Item = Obstacle> + '_ {
+        let now = current_time();
+        self.reports.values().map(move |(report, t)| Obstacle {
+            pos: report.pos + report.vel * (now - t),
+            vel: report.vel,
+            radius: ship_dim(report.class) / 2.,
+        })
+    }
+}
+
+pub struct CollisionAvoidance {
+    friendlies: Friendlies,
+    // The gap we keep between our hull and anything else's.
+    pub margin: f64,
+}
+
+impl Default for CollisionAvoidance {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl CollisionAvoidance {
+    pub fn new() -> CollisionAvoidance {
+        CollisionAvoidance {
+            friendlies: Friendlies::new(),
+            margin: 50.,
+        }
+    }
+
+    // Changes the acceleration commanded this tick if it would put us on a
+    // collision course. Returns whether it did.
+    pub fn tick(&mut self, contacts: &Contacts) -> bool {
+        self.friendlies.tick();
+        let obstacles: Vec<Obstacle> = contacts
+            .iter()
+            .map(|c| Obstacle {
+                pos: c.pos(),
+                vel: c.vel(),
+                radius: ship_dim(c.class()) / 2.,
+            })
+            .chain(self.friendlies.obstacles())
+            .collect();
+        let preferred = velocity() + commanded_acceleration() * TICK_LENGTH;
+        let radius = ship_dim(class()) / 2. + self.margin;
+        let safe = avoid(position(), preferred, radius, &obstacles);
+        if safe == preferred {
+            return false;
+        }
+        match_velocity(safe);
+        draw_line(position(), position() + safe, 0xffff00);
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{flight::fly_to, sim::Sim, test_env::*};
+
+    fn asteroid(pos: Vec2) -> Obstacle {
+        Obstacle {
+            pos,
+            vel: vec2(0., 0.),
+            radius: ship_dim(Class::Asteroid) / 2.,
+        }
+    }
+
+    #[test]
+    fn time_to_collision_head_on() {
+        let o = asteroid(vec2(1000., 0.));
+        let t = time_to_collision(vec2(0., 0.), vec2(100., 0.), 10., &o).unwrap();
+        assert!((t - 9.4).abs() < 1e-9, "{}", t);
+        // Passing wide, going away and standing still.
+        assert_eq!(
+            time_to_collision(vec2(0., 0.), vec2(100., 10.), 10., &o),
+            None
+        );
+        assert_eq!(
+            time_to_collision(vec2(0., 0.), vec2(-100., 0.), 10., &o),
+            None
+        );
+        assert_eq!(time_to_collision(vec2(0., 0.), vec2(0., 0.), 10., &o), None);
+    }
+
+    #[test]
+    fn clear_path_is_unchanged() {
+        let obstacles = [asteroid(vec2(1000., 500.))];
+        let v = vec2(100., 0.);
+        assert_eq!(avoid(vec2(0., 0.), v, 10., &obstacles), v);
+    }
+
+    #[test]
+    fn steers_around_obstacle_ahead() {
+        let obstacles = [asteroid(vec2(1000., 10.))];
+        let preferred = vec2(200., 0.);
+        let v = avoid(vec2(0., 0.), preferred, 60., &obstacles);
+        assert!(time_to_collision(vec2(0., 0.), v, 60., &obstacles[0]).is_none());
+        // Around the side the obstacle is furthest from, without giving up
+        // much speed.
+        assert!(v.y < 0., "{:?}", v);
+        assert!(v.length() > 150., "{:?}", v);
+    }
+
+    #[test]
+    fn slows_down_when_boxed_in() {
+        // A wall of asteroids ahead, too wide to go around before we hit it.
+        let obstacles: Vec<Obstacle> = (-20..=20)
+            .map(|i| asteroid(vec2(500., i as f64 * 80.)))
+            .collect();
+        let v = avoid(vec2(0., 0.), vec2(100., 0.), 10., &obstacles);
+        assert!(v.x < 100., "{:?}", v);
+        let first = obstacles
+            .iter()
+            .filter_map(|o| time_to_collision(vec2(0., 0.), v, 10., o))
+            .fold(f64::INFINITY, f64::min);
+        assert!(first >= HORIZON, "{:?} {}", v, first);
+    }
+
+    #[test]
+    fn reports_round_trip_over_radio() {
+        let mut env = Env::new(Class::Frigate);
+        env.set_position(vec2(100., -200.));
+        env.set_velocity(vec2(3., 4.));
+        let report = Report {
+            id: 3,
+            class: Class::Cruiser,
+            pos: vec2(1000.5, -2000.25),
+            vel: vec2(10., -5.),
+        };
+        assert_eq!(Report::decode(&report.encode()), Some(report));
+        assert_eq!(Report::decode(&[0; 32]), None);
+
+        // Our own report comes back as if it were someone else's.
+        let mut friendlies = Friendlies::new();
+        friendlies.tick();
+        assert_eq!(friendlies.obstacles().count(), 0);
+        select_radio(RADIO);
+        let sent = Report {
+            id: 3,
+            ..Report::decode(&receive_sent()).unwrap()
+        };
+        assert_eq!(sent.pos, vec2(100., -200.));
+        assert_eq!(sent.class, Class::Frigate);
+        send_bytes(&sent.encode());
+        env.loop_back_radio();
+        env.advance_ticks(1);
+        friendlies.tick();
+        let obstacles: Vec<Obstacle> = friendlies.obstacles().collect();
+        assert_eq!(obstacles.len(), 1);
+        assert_eq!(obstacles[0].radius, 120.);
+        // Extrapolated from when the report was sent.
+        env.advance_ticks(29);
+        let pos = friendlies.obstacles().next().unwrap().pos;
+        assert!((pos - vec2(101.5, -198.)).length() < 1e-6, "{:?}", pos);
+
+        // Reports time out.
+        env.advance_ticks((REPORT_TIMEOUT / TICK_LENGTH) as u32);
+        friendlies.tick();
+        assert_eq!(friendlies.obstacles().count(), 0);
+    }
+
+    #[test]
+    fn restores_selected_radio() {
+        let _env = Env::new(Class::Frigate);
+        select_radio(2);
+        set_radio_channel(3);
+        Friendlies::new().tick();
+        let selected = oort_api::sys::read_system_state(oort_api::SystemState::SelectedRadio);
+        assert_eq!(selected, 2.);
+        // The other radio's channel is left alone too.
+        assert_eq!(get_radio_channel(), 3);
+    }
+
+    // Reads back the message sent on the selected radio this tick.
+    fn receive_sent() -> [u8; 32] {
+        let sel = oort_api::sys::read_system_state(oort_api::SystemState::SelectedRadio) as usize;
+        let indices = radio_internal::radio_indices(sel);
+        oort_api::sys::write_system_state(indices.receive, 1.);
+        let bytes = receive_bytes().unwrap();
+        oort_api::sys::write_system_state(indices.receive, 0.);
+        bytes
+    }
+
+    #[test]
+    fn flies_around_asteroid_in_the_way() {
+        let mut sim = Sim::new(Class::Fighter);
+        let rock = vec2(1500., 20.);
+        sim.add_target(Class::Asteroid, rock, vec2(0., 0.));
+        let goal = vec2(3000., 0.);
+        let mut contacts = Contacts::new();
+        let mut avoidance = CollisionAvoidance::new();
+        let mut closest = f64::INFINITY;
+        let arrived = (0..(40. / TICK_LENGTH) as u32).any(|_| {
+            sim.step(|| {
+                contacts.tick();
+                contacts.recv_contacts(vec![scan_result(Class::Asteroid, rock, vec2(0., 0.))]);
+                fly_to(goal, vec2(0., 0.), f64::INFINITY);
+                avoidance.tick(&contacts);
+            });
+            closest = closest.min((sim.ship.pos - rock).length());
+            (sim.ship.pos - goal).length() < 10. && sim.ship.vel.length() < 1.
+        });
+        assert!(arrived);
+        let contact_distance = (ship_dim(Class::Fighter) + ship_dim(Class::Asteroid)) / 2.;
+        assert!(closest > contact_distance, "{}", closest);
+    }
+}
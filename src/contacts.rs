@@ -1,127 +1,764 @@
-use crate::control::*;
-use oort_api::{
-    ClassStats,
-    prelude::{
-        maths_rs::{
-            mat::*,
-            num::{Number, NumberOps, SignedNumber},
-            *,
-        },
-        *,
-    },
-};
+use crate::{assignment::hungarian, matrix::*};
+use oort_api::prelude::*;
 use std::collections::{HashMap, VecDeque};
 
-#[derive(Debug)]
+// Number of state variables tracked per axis: position, velocity and
+// acceleration.
+const AXIS_DIM: usize = 3;
+const STATE_DIM: usize = 2 * AXIS_DIM;
+// The radar gives us a position and a velocity. We treat the position as a
+// range and bearing from our ship since that is how its error behaves, and the
+// velocity as a velocity on each axis.
+const MEASUREMENT_DIM: usize = 4;
+
+type State = Vector<STATE_DIM>;
+type StateMatrix = Matrix<STATE_DIM, STATE_DIM>;
+type Measurement = Vector<MEASUREMENT_DIM>;
+type MeasurementMatrix = Matrix<MEASUREMENT_DIM, MEASUREMENT_DIM>;
+
+// The kinematic model a KalmanFilter assumes the contact follows between
+// updates.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum MotionModel {
+    // The contact coasts. Acceleration is treated as small white noise and is
+    // not part of the tracked state.
+    ConstantVelocity,
+    // The contact is burning. Acceleration is tracked and the change in
+    // acceleration is treated as white noise.
+    ConstantAcceleration,
+}
+
+#[derive(Clone, Debug)]
 struct KalmanFilter {
     // The state of the enemy contact.
-    // pos_x, pos_y, vel_x, vel_y
-    state: Vec4f,
+    // pos_x, vel_x, acc_x, pos_y, vel_y, acc_y
+    state: State,
 
     // The state covariance matrix: represents the uncertainty in the state.
     // Also called P in the literature.
-    state_covariance: Mat4f,
+    state_covariance: StateMatrix,
+
+    // The process covariance matrix per timestep for a given ship class. Also
+    // called Q in the literature. It is multiplied by process_noise_scale,
+    // which the Imm adapts for the constant acceleration model depending on
+    // how well the track has been predicting.
+    process_covariance: StateMatrix,
+    process_noise_scale: f64,
 
-    // The process covariance matrix is fixed per timestep for a given ship
-    // class. Also called Q in the literature.
-    process_covariance: Mat4f,
+    // The variance of an acceleration we know nothing about, beyond the
+    // ship's limits. Zero if the model doesn't track acceleration.
+    acc_var: f64,
 
-    transition: Mat4f,
-    transition_transposed: Mat4f,
+    transition: StateMatrix,
+    transition_transposed: StateMatrix,
 }
 
-fn add<T: Number>(mut a: Mat4<T>, b: &Mat4<T>) -> Mat4<T> {
-    for i in 0..16 {
-        a[i] += b[i];
-    }
-    a
+// The chi-square value with MEASUREMENT_DIM degrees of freedom below which 99%
+// of the innovations of a correctly matched scan will fall. Scans whose
+// squared Mahalanobis distance from a contact exceeds this are assumed to
+// belong to something else.
+pub const GATE_THRESHOLD: f64 = 13.28;
+
+// A radar return, along with where we were when we received it.
+#[derive(Clone, Copy, Debug)]
+struct Observation {
+    origin: Vec2,
+    pos: Vec2,
+    vel: Vec2,
+    snr: f64,
 }
 
-fn neg<T: SignedNumber>(mut a: Mat4<T>) -> Mat4<T> {
-    for i in 0..16 {
-        a[i] = -a[i];
+impl Observation {
+    fn from_scan(scan: &ScanResult) -> Observation {
+        Observation {
+            origin: position(),
+            pos: scan.position,
+            vel: scan.velocity,
+            snr: scan.snr,
+        }
+    }
+
+    // The observation in measurement space: range, bearing, vel_x, vel_y.
+    fn measurement(&self) -> Measurement {
+        let rel = self.pos - self.origin;
+        Measurement::new([rel.length(), rel.angle(), self.vel.x, self.vel.y])
     }
-    a
 }
 
-// Returns measurement noise covariance matrix.
-fn calculate_measurement_covariance(snr: f64) -> Mat4f {
-    let error_factor = 10.0f32.powf(-snr as f32 / 10.0);
-    const DISTANCE_NOISE_FACTOR: f32 = 1e4;
-    let pos_var = (DISTANCE_NOISE_FACTOR * error_factor).powi(2);
-    const VELOCITY_NOISE_FACTOR: f32 = 1e2;
+// Returns the measurement noise covariance matrix in measurement space: range,
+// bearing, vel_x, vel_y. Every standard deviation is proportional to
+// 10^(-snr/10). The range error doesn't grow with distance but the error
+// across the beam does, being the bearing error times the range, so beyond
+// RANGE_NOISE_FACTOR / BEARING_NOISE_FACTOR = 1 km the uncertainty is an
+// ellipse stretched across the beam. That covers just about every contact
+// worth tracking.
+fn calculate_measurement_covariance(snr: f64) -> MeasurementMatrix {
+    let error_factor = 10.0f64.powf(-snr / 10.0);
+    const RANGE_NOISE_FACTOR: f64 = 1e3;
+    let range_var = (RANGE_NOISE_FACTOR * error_factor).powi(2);
+    const BEARING_NOISE_FACTOR: f64 = 1.;
+    let bearing_var = (BEARING_NOISE_FACTOR * error_factor).powi(2);
+    const VELOCITY_NOISE_FACTOR: f64 = 1e2;
     let vel_var = (VELOCITY_NOISE_FACTOR * error_factor).powi(2);
+    MeasurementMatrix::diagonal([range_var, bearing_var, vel_var, vel_var])
+}
+
+// Returns the measurement noise covariance of the observation converted to
+// cartesian coordinates: pos_x, vel_x, pos_y, vel_y.
+fn cartesian_measurement_covariance(obs: &Observation) -> MeasurementMatrix {
+    let rel = obs.pos - obs.origin;
+    let r = rel.length();
+    let (s, c) = (rel.angle()).sin_cos();
+    // The jacobian of the conversion from range, bearing, vel_x, vel_y.
+    #[rustfmt::skip]
+    let j = MeasurementMatrix::from_rows([
+        [c,  -r * s, 0., 0.],
+        [0., 0.,     1., 0.],
+        [s,  r * c,  0., 0.],
+        [0., 0.,     0., 1.],
+    ]);
+    j * calculate_measurement_covariance(obs.snr) * j.transpose()
+}
+
+// Maps the state onto cartesian pos_x, vel_x, pos_y, vel_y.
+#[rustfmt::skip]
+fn cartesian_observation() -> Matrix<MEASUREMENT_DIM, STATE_DIM> {
+    Matrix::from_rows([
+        [1., 0., 0., 0., 0., 0.],
+        [0., 1., 0., 0., 0., 0.],
+        [0., 0., 0., 1., 0., 0.],
+        [0., 0., 0., 0., 1., 0.],
+    ])
+}
+
+// Returns the measurement we expect to see for the given state, observed from
+// origin, along with the jacobian of the measurement function at that state.
+// The jacobian is the linearized observation matrix, also called H in the
+// literature.
+fn predict_measurement(
+    state: &State,
+    origin: Vec2,
+) -> (Measurement, Matrix<MEASUREMENT_DIM, STATE_DIM>) {
+    let rel = state_pos(state) - origin;
+    let dx = rel.x;
+    let dy = rel.y;
+    // Keep the jacobian finite if the contact is right on top of us.
+    let r2 = (dx * dx + dy * dy).max(1.);
+    let r = r2.sqrt();
+    let predicted = Measurement::new([r, dy.atan2(dx), state[1], state[4]]);
     #[rustfmt::skip]
-    return Mat4f::new(
-        pos_var, 0., 0., 0.,
-        0., vel_var, 0., 0.,
-        0., 0., pos_var, 0.,
-        0., 0., 0., vel_var,
-    );
+    let h = Matrix::from_rows([
+        [dx / r,   0., 0., dy / r,  0., 0.],
+        [-dy / r2, 0., 0., dx / r2, 0., 0.],
+        [0.,       1., 0., 0.,      0., 0.],
+        [0.,       0., 0., 0.,      1., 0.],
+    ]);
+    (predicted, h)
+}
+
+// Returns the innovation (the difference between the observation and where
+// the estimate says the observation should be), its covariance and the
+// linearized observation matrix. Also called y, S and H in the literature.
+fn innovation(
+    state: &State,
+    state_covariance: &StateMatrix,
+    obs: &Observation,
+) -> (
+    Measurement,
+    MeasurementMatrix,
+    Matrix<MEASUREMENT_DIM, STATE_DIM>,
+) {
+    let (predicted, h) = predict_measurement(state, obs.origin);
+    let mut y = obs.measurement() - predicted;
+    // The bearing residual needs to take the short way around the circle.
+    y[1] = angle_diff(predicted[1], obs.measurement()[1]);
+    let s = h * *state_covariance * h.transpose() + calculate_measurement_covariance(obs.snr);
+    (y, s, h)
+}
+
+// Returns the squared Mahalanobis distance of an innovation, or None if the
+// innovation covariance is singular.
+fn mahalanobis_sq<const N: usize>(
+    innovation: &Vector<N>,
+    covariance: &Matrix<N, N>,
+) -> Option<f64> {
+    Some(innovation.dot(&(covariance.inverse()? * *innovation)))
+}
+
+fn state_pos(state: &State) -> Vec2 {
+    Vec2::new(state[0], state[3])
+}
+
+fn state_vel(state: &State) -> Vec2 {
+    Vec2::new(state[1], state[4])
+}
+
+fn state_acc(state: &State) -> Vec2 {
+    Vec2::new(state[2], state[5])
+}
+
+// Copies a per-axis block into both the x and y blocks of a state matrix.
+fn per_axis(block: [[f64; AXIS_DIM]; AXIS_DIM]) -> StateMatrix {
+    let mut m = StateMatrix::zeros();
+    for axis in 0..2 {
+        let o = axis * AXIS_DIM;
+        for (r, row) in block.iter().enumerate() {
+            for (c, v) in row.iter().enumerate() {
+                m[(o + r, o + c)] = *v;
+            }
+        }
+    }
+    m
+}
+
+// Scales a filter's process noise based on its recent innovations. When the
+// innovations are bigger than the filter expects the target is doing
+// something the model doesn't capture (e.g. it started maneuvering), so we
+// inflate the process noise. When they are smaller we shrink it to get a
+// tighter track, though never by much: a run of clean scans says little about
+// what the target will do next.
+//
+// To use it, multiply the process covariance by scale() when predicting and
+// report the normalized innovation squared of every observation to observe()
+// before updating with it.
+#[derive(Clone, Debug)]
+pub struct AdaptiveProcessNoise {
+    scale: f64,
+    min_scale: f64,
+    max_scale: f64,
+
+    // Exponential moving average of the normalized innovation squared,
+    // normalized again by the number of degrees of freedom so that it
+    // should hover around 1.
+    average_ratio: f64,
+}
+
+impl AdaptiveProcessNoise {
+    // How much weight each new innovation gets in the moving average.
+    const SMOOTHING: f64 = 0.2;
+    // How aggressively we chase the moving average. Kept low so a single
+    // surprising scan can't blow the track wide open.
+    const ADAPT_RATE: f64 = 0.5;
+    // The most any one observation can contribute to the moving average, as
+    // a multiple of what the filter expects. High enough that the first scan
+    // after a target starts burning registers as a big surprise.
+    const MAX_RATIO: f64 = 25.;
+
+    pub fn new(min_scale: f64, max_scale: f64) -> AdaptiveProcessNoise {
+        AdaptiveProcessNoise {
+            scale: 1.,
+            min_scale,
+            max_scale,
+            average_ratio: 1.,
+        }
+    }
+
+    // The factor to multiply the nominal process covariance by.
+    pub fn scale(&self) -> f64 {
+        self.scale
+    }
+
+    // Records the normalized innovation squared of an update with dof degrees
+    // of freedom.
+    pub fn observe(&mut self, nis: f64, dof: usize) {
+        if !nis.is_finite() {
+            return;
+        }
+        // Cap the contribution of any one update so that an outlier doesn't
+        // dominate the average.
+        let ratio = (nis / dof as f64).min(Self::MAX_RATIO);
+        self.average_ratio += Self::SMOOTHING * (ratio - self.average_ratio);
+        self.scale = (self.scale * self.average_ratio.powf(Self::ADAPT_RATE))
+            .clamp(self.min_scale, self.max_scale);
+    }
+}
+
+impl Default for AdaptiveProcessNoise {
+    fn default() -> Self {
+        AdaptiveProcessNoise::new(0.5, 20.)
+    }
 }
 
 impl KalmanFilter {
-    pub fn new(class: Class, pos: Vec2, vel: Vec2, snr: f64) -> KalmanFilter {
+    pub fn new(class: Class, model: MotionModel, obs: &Observation) -> KalmanFilter {
         let max_accel = class.default_stats().max_forward_acceleration;
-        let accel_var = (max_accel * 2. / 12.).powi(2) as f32;
-        let tick2 = TICK_LENGTH.powi(2) as f32;
-        let tick3 = TICK_LENGTH.powi(3) as f32;
-        let tick4 = TICK_LENGTH.powi(4) as f32;
-
-        #[rustfmt::skip]
-        let process_covariance = Mat4f::new(
-            0.25f32 * tick4 * accel_var, 0.5 * tick3 * accel_var, 0., 0.,
-            0.5f32 * tick3 * accel_var, tick2 * accel_var, 0., 0.,
-            0., 0., 0.25f32 * tick4 * accel_var, 0.5 * tick3 * accel_var,
-            0., 0., 0.5f32 * tick3 * accel_var, tick2 * accel_var,
-        );
+        let dt = TICK_LENGTH;
+        let dt2 = dt.powi(2);
+        let dt3 = dt.powi(3);
+        let dt4 = dt.powi(4);
+        let dt5 = dt.powi(5);
 
-        #[rustfmt::skip]
-        let transition = Mat4f::new(
-            1.0, TICK_LENGTH as f32, 0., 0.,
-            0., 1., 0., 0.,
-            0., 0., 1., TICK_LENGTH as f32,
-            0., 0., 0., 1.,
-        );
+        let (process_covariance, transition, acc_var) = match model {
+            MotionModel::ConstantVelocity => {
+                // A coasting ship may still be making small corrections.
+                // Treat them as white noise acceleration. The acceleration
+                // state is pinned to zero.
+                let accel_var = (max_accel * 0.05).powi(2);
+                #[rustfmt::skip]
+                let process_covariance = per_axis([
+                    [0.25 * dt4, 0.5 * dt3, 0.],
+                    [0.5 * dt3,  dt2,       0.],
+                    [0.,         0.,        0.],
+                ]) * accel_var;
+                #[rustfmt::skip]
+                let transition = per_axis([
+                    [1., dt, 0.],
+                    [0., 1., 0.],
+                    [0., 0., 0.],
+                ]);
+                (process_covariance, transition, 0.)
+            }
+            MotionModel::ConstantAcceleration => {
+                // We model the change in acceleration as white noise (the
+                // continuous white noise jerk model). accel_var is how far we
+                // expect the acceleration to wander over the course of a
+                // second.
+                let accel_var = (max_accel * 2. / 12.).powi(2);
+                #[rustfmt::skip]
+                let process_covariance = per_axis([
+                    [dt5 / 20., dt4 / 8., dt3 / 6.],
+                    [dt4 / 8.,  dt3 / 3., dt2 / 2.],
+                    [dt3 / 6.,  dt2 / 2., dt],
+                ]) * accel_var;
+                #[rustfmt::skip]
+                let transition = per_axis([
+                    [1., dt, 0.5 * dt2],
+                    [0., 1., dt],
+                    [0., 0., 1.],
+                ]);
+                // We know nothing about the acceleration except that the
+                // ship can't exceed its maximum.
+                (process_covariance, transition, max_accel.powi(2))
+            }
+        };
 
         // Use the RSSI to calculate the initial noise on the position and
         // velocity.
+        let h = cartesian_observation();
+        let initial_covariance = process_covariance
+            + h.transpose() * cartesian_measurement_covariance(obs) * h
+            + StateMatrix::diagonal([0., 0., acc_var, 0., 0., acc_var]);
         KalmanFilter {
-            state: Vec4f::new(pos.x as f32, vel.x as f32, pos.y as f32, vel.y as f32),
-            state_covariance: add(process_covariance, &calculate_measurement_covariance(snr)),
+            state: State::new([obs.pos.x, obs.vel.x, 0., obs.pos.y, obs.vel.y, 0.]),
+            state_covariance: initial_covariance,
             process_covariance,
+            process_noise_scale: 1.,
+            acc_var,
             transition,
             transition_transposed: transition.transpose(),
         }
     }
 
+    // Updates the predicted contact state and covariance.
+    pub fn predict(&mut self) {
+        self.state = self.transition * self.state;
+        self.state_covariance =
+            (self.transition * self.state_covariance * self.transition_transposed
+                + self.process_covariance * self.process_noise_scale)
+            .symmetrized();
+    }
+
+    // Updates the filter with a new observation. Returns the log-likelihood of
+    // the observation under this filter's prediction, or None if the
+    // observation could not be used.
+    //
+    // This is an extended Kalman filter update: the measurement function is
+    // linearized around the predicted state.
+    pub fn update(&mut self, obs: &Observation) -> Option<f64> {
+        let (innovation, innovation_covariance, h) =
+            innovation(&self.state, &self.state_covariance, obs);
+        // The measurement is unusable if this fails. Keep coasting on the
+        // prediction.
+        let innovation_covariance_inv = innovation_covariance.inverse()?;
+        let kalman_gain = self.state_covariance * h.transpose() * innovation_covariance_inv;
+        self.state += kalman_gain * innovation;
+        // Use the Joseph form of the covariance update. It's more expensive
+        // than (I - KH)P but stays symmetric positive definite in the face of
+        // rounding errors, which add up over a long engagement.
+        let i_kh = StateMatrix::identity() - kalman_gain * h;
+        let measurement_covariance = calculate_measurement_covariance(obs.snr);
+        let joseph = i_kh * self.state_covariance * i_kh.transpose()
+            + kalman_gain * measurement_covariance * kalman_gain.transpose();
+        self.state_covariance = joseph.symmetrized();
+
+        let distance_sq = innovation.dot(&(innovation_covariance_inv * innovation));
+        Some(
+            -0.5 * (distance_sq
+                + innovation_covariance.determinant().ln()
+                + MEASUREMENT_DIM as f64 * (2. * std::f64::consts::PI).ln()),
+        )
+    }
+}
+
+// How long we expect a contact to spend in each motion model before switching
+// to the other one, in seconds.
+const MEAN_COAST_DURATION: f64 = 4.;
+const MEAN_MANEUVER_DURATION: f64 = 2.;
+
+// An Interacting Multiple Model estimator. Runs a constant velocity filter and
+// a constant acceleration filter side by side and blends them according to how
+// well each one has been explaining the observations. This lets us track a
+// coasting ship tightly without falling behind when it starts burning.
+#[derive(Debug)]
+struct Imm {
+    // Indexed by the MotionModel discriminant.
+    filters: [KalmanFilter; 2],
+
+    // The probability that the contact is currently following each model.
+    mode_probabilities: [f64; 2],
+
+    // The per-tick Markov chain of switching between models. Entry [i][j] is
+    // the probability of switching from model i to model j.
+    switch_probabilities: [[f64; 2]; 2],
+
+    // The combined estimate across all models.
+    state: State,
+    state_covariance: StateMatrix,
+
+    // Adapts the process noise of the constant acceleration model to how
+    // surprising each observation is to the combined estimate. The constant
+    // velocity model keeps its nominal process noise: if it could inflate to
+    // follow a maneuver, the mode probabilities would no longer tell us
+    // whether the contact is maneuvering.
+    process_noise: AdaptiveProcessNoise,
+}
+
+impl Imm {
+    pub fn new(class: Class, obs: &Observation) -> Imm {
+        let dt = TICK_LENGTH;
+        let leave_coast = dt / MEAN_COAST_DURATION;
+        let leave_maneuver = dt / MEAN_MANEUVER_DURATION;
+        let mut imm = Imm {
+            filters: [
+                KalmanFilter::new(class, MotionModel::ConstantVelocity, obs),
+                KalmanFilter::new(class, MotionModel::ConstantAcceleration, obs),
+            ],
+            mode_probabilities: [0.5, 0.5],
+            switch_probabilities: [
+                [1. - leave_coast, leave_coast],
+                [leave_maneuver, 1. - leave_maneuver],
+            ],
+            state: State::zeros(),
+            state_covariance: StateMatrix::zeros(),
+            process_noise: AdaptiveProcessNoise::default(),
+        };
+        imm.combine();
+        imm
+    }
+
     pub fn pos(&self) -> Vec2 {
-        Vec2::new(self.state[0] as f64, self.state[2] as f64)
+        state_pos(&self.state)
     }
 
     pub fn vel(&self) -> Vec2 {
-        Vec2::new(self.state[1] as f64, self.state[3] as f64)
+        state_vel(&self.state)
     }
 
-    // Updates the predicted contact state and covariance.
+    pub fn acc(&self) -> Vec2 {
+        state_acc(&self.state)
+    }
+
+    // The probability that the contact is following the constant
+    // acceleration model.
+    pub fn maneuvering_probability(&self) -> f64 {
+        self.mode_probabilities[MotionModel::ConstantAcceleration as usize]
+    }
+
+    // Returns the squared Mahalanobis distance between an observation and
+    // the model that best predicted it. The combined estimate is a poor
+    // stand-in for the mixture when the models disagree: the first scan
+    // after a contact starts burning is well within what the maneuvering
+    // model allows, but far outside the combined covariance while the
+    // contact is most likely coasting.
+    pub fn distance_sq(&self, obs: &Observation) -> Option<f64> {
+        self.filters
+            .iter()
+            .filter_map(|f| {
+                let (y, s, _) = innovation(&f.state, &f.state_covariance, obs);
+                mahalanobis_sq(&y, &s)
+            })
+            .reduce(f64::min)
+    }
+
+    // Returns the normalized innovation squared of an observation against the
+    // combined estimate.
+    pub fn nis(&self, obs: &Observation) -> Option<f64> {
+        let (y, s, _) = innovation(&self.state, &self.state_covariance, obs);
+        mahalanobis_sq(&y, &s)
+    }
+
+    // Returns the normalized innovation squared of an observation against
+    // each model, weighted by the model's probability. The combined
+    // covariance includes the spread of the models, so while a coasting
+    // contact keeps a diffuse maneuvering model around nis() comes out low
+    // even when the filter is well tuned. This doesn't.
+    pub fn model_nis(&self, obs: &Observation) -> Option<f64> {
+        let mut total = 0.;
+        for (f, p) in self.filters.iter().zip(self.mode_probabilities) {
+            let (y, s, _) = innovation(&f.state, &f.state_covariance, obs);
+            total += p * mahalanobis_sq(&y, &s)?;
+        }
+        Some(total)
+    }
+
+    // Returns the squared Mahalanobis distance between two estimates, under
+    // the hypothesis that they describe the same object.
+    pub fn distance_sq_to(&self, other: &Imm) -> Option<f64> {
+        let h = cartesian_observation();
+        let d = h * (self.state - other.state);
+        let s = h * (self.state_covariance + other.state_covariance) * h.transpose();
+        mahalanobis_sq(&d, &s)
+    }
+
+    // Mixes the model estimates according to the switching probabilities and
+    // then predicts each model forward one tick.
     pub fn predict(&mut self) {
-        self.state = self.transition * self.state;
-        self.state_covariance = add(
-            self.transition * self.state_covariance * self.transition_transposed,
-            &self.process_covariance,
-        );
+        let n = self.filters.len();
+        let mut predicted_probabilities = [0.; 2];
+        for (j, p) in predicted_probabilities.iter_mut().enumerate() {
+            *p = (0..n)
+                .map(|i| self.switch_probabilities[i][j] * self.mode_probabilities[i])
+                .sum();
+        }
+
+        // Every model's mixed estimate is computed from the unmixed ones, so
+        // don't write any back until they're all done.
+        let mixed: [Option<(State, StateMatrix)>; 2] = std::array::from_fn(|j| {
+            if predicted_probabilities[j] <= 0. {
+                return None;
+            }
+            let weights: [f64; 2] = std::array::from_fn(|i| {
+                self.switch_probabilities[i][j] * self.mode_probabilities[i]
+                    / predicted_probabilities[j]
+            });
+            let coasting = weights[MotionModel::ConstantVelocity as usize];
+            let (state, covariance) = mix(self.filters.iter().zip(weights));
+            // The constant velocity estimate says nothing about acceleration,
+            // not that it's exactly zero. Without this a contact that starts
+            // burning out of a coast is far outside every model's prediction.
+            let acc_var = self.filters[j].acc_var;
+            let covariance =
+                covariance + StateMatrix::diagonal([0., 0., acc_var, 0., 0., acc_var]) * coasting;
+            Some((state, covariance))
+        });
+        for (filter, mixed) in self.filters.iter_mut().zip(mixed) {
+            if let Some((state, covariance)) = mixed {
+                filter.state = state;
+                filter.state_covariance = covariance;
+            }
+        }
+        self.mode_probabilities = predicted_probabilities;
+
+        for filter in &mut self.filters {
+            filter.predict();
+        }
+        self.combine();
+    }
+
+    // Updates every model with the observation and reweights the models by
+    // how likely each one thought the observation was.
+    pub fn update(&mut self, obs: &Observation) {
+        if let Some(nis) = self.nis(obs) {
+            self.process_noise.observe(nis, MEASUREMENT_DIM);
+            self.filters[MotionModel::ConstantAcceleration as usize].process_noise_scale =
+                self.process_noise.scale();
+        }
+
+        let mut log_likelihoods = [f64::NEG_INFINITY; 2];
+        for (filter, l) in self.filters.iter_mut().zip(log_likelihoods.iter_mut()) {
+            if let Some(ll) = filter.update(obs) {
+                *l = ll;
+            }
+        }
+
+        // Normalize in log space. The likelihoods of a surprising observation
+        // easily underflow a float.
+        let max_ll = log_likelihoods
+            .iter()
+            .cloned()
+            .fold(f64::NEG_INFINITY, f64::max);
+        if max_ll.is_finite() {
+            let mut total = 0.;
+            for (p, l) in self.mode_probabilities.iter_mut().zip(log_likelihoods) {
+                *p *= (l - max_ll).exp();
+                total += *p;
+            }
+            if total > 0. {
+                for p in &mut self.mode_probabilities {
+                    *p /= total;
+                }
+            }
+        }
+        self.combine();
+    }
+
+    // Recomputes the combined estimate from the per-model estimates.
+    fn combine(&mut self) {
+        let (state, covariance) = mix(self.filters.iter().zip(self.mode_probabilities));
+        self.state = state;
+        self.state_covariance = covariance;
+    }
+}
+
+// Returns the weighted mean and covariance of a mixture of filter estimates,
+// including the spread between the estimates.
+fn mix<'a>(
+    estimates: impl Iterator<Item = (&'a KalmanFilter, f64)> + Clone,
+) -> (State, StateMatrix) {
+    let mut state = State::zeros();
+    for (f, w) in estimates.clone() {
+        state += f.state * w;
+    }
+    let mut covariance = StateMatrix::zeros();
+    for (f, w) in estimates {
+        let d = f.state - state;
+        covariance += (f.state_covariance + d * d.transpose()) * w;
+    }
+    (state, covariance)
+}
+
+// How many updates the rolling average of the normalized innovation squared
+// covers.
+const NIS_WINDOW: usize = 20;
+
+// Whether a contact's filter uncertainty matches the errors it is actually
+// making, judged by its normalized innovation squared (NIS).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Consistency {
+    // The innovations are larger than the filter expected. The covariance is
+    // too small: we are overconfident.
+    Overconfident,
+    Consistent,
+    // The innovations are smaller than the filter expected. The covariance is
+    // too large: we are underconfident.
+    Underconfident,
+}
+
+// Things Contact::draw can optionally show.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct DrawOptions {
+    // Show the normalized innovation squared and color the contact by
+    // whether its filter is consistent.
+    pub consistency: bool,
+
+    // Show the smoothed trajectory of the contact over its recent history.
+    pub trail: bool,
+}
+
+// How many updates we remember for each contact.
+const HISTORY_LEN: usize = 64;
+
+// One update of a contact: the scan we received and the estimate of the
+// contact immediately before and after we incorporated it.
+#[derive(Clone, Debug)]
+struct HistoryEntry {
+    tick: u32,
+    scan: ScanResult,
+    prior_state: State,
+    prior_covariance: StateMatrix,
+    state: State,
+    state_covariance: StateMatrix,
+}
+
+// A point on the reconstructed trajectory of a contact.
+#[derive(Clone, Copy, Debug)]
+pub struct TrackPoint {
+    pub time: f64,
+    pub pos: Vec2,
+    pub vel: Vec2,
+    pub acc: Vec2,
+}
+
+impl TrackPoint {
+    fn new(tick: u32, state: &State) -> TrackPoint {
+        TrackPoint {
+            time: tick as f64 * TICK_LENGTH,
+            pos: state_pos(state),
+            vel: state_vel(state),
+            acc: state_acc(state),
+        }
+    }
+}
+
+// Runs a Rauch-Tung-Striebel smoother backwards over the history, returning
+// the smoothed state and covariance at each entry. Each estimate benefits from
+// the scans that came after it as well as the ones before.
+//
+// The IMM doesn't have a single transition model, so we assume constant
+// acceleration between entries. The priors we recorded already include the
+// effect of model mixing, which keeps this a reasonable approximation.
+fn rts_smooth(history: &VecDeque<HistoryEntry>) -> Vec<(State, StateMatrix)> {
+    let Some(last) = history.back() else {
+        return Vec::new();
+    };
+    let mut smoothed = vec![(last.state, last.state_covariance); history.len()];
+    let mut next_state = last.state;
+    let mut next_covariance = last.state_covariance;
+    for k in (0..history.len() - 1).rev() {
+        let cur = &history[k];
+        let next = &history[k + 1];
+        let dt = (next.tick - cur.tick) as f64 * TICK_LENGTH;
+        #[rustfmt::skip]
+        let transition = per_axis([
+            [1., dt, 0.5 * dt * dt],
+            [0., 1., dt],
+            [0., 0., 1.],
+        ]);
+        let Some(prior_inv) = next.prior_covariance.inverse() else {
+            // Can't smooth across this gap. Fall back on the filtered
+            // estimate and start over from here.
+            smoothed[k] = (cur.state, cur.state_covariance);
+            next_state = cur.state;
+            next_covariance = cur.state_covariance;
+            continue;
+        };
+        let gain = cur.state_covariance * transition.transpose() * prior_inv;
+        next_state = cur.state + gain * (next_state - next.prior_state);
+        next_covariance = (cur.state_covariance
+            + gain * (next_covariance - next.prior_covariance) * gain.transpose())
+        .symmetrized();
+        smoothed[k] = (next_state, next_covariance);
     }
+    smoothed
+}
+
+// Where a contact is in its life. New contacts start out tentative since a
+// single scan may be noise or a duplicate. Once we have seen them often enough
+// they are confirmed. Confirmed contacts that we fail to find coast on their
+// prediction for a while before being deleted.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TrackState {
+    Tentative,
+    Confirmed,
+    Coasting,
+    Deleted,
+}
+
+// The rules for confirming and deleting contacts.
+#[derive(Clone, Copy, Debug)]
+pub struct TrackPolicy {
+    // A tentative contact is confirmed once it has been found on at least
+    // confirm_hits out of the last confirm_window attempts to track it
+    // (including the scan that created it).
+    pub confirm_hits: u32,
+    pub confirm_window: u32,
 
-    // Updates the filter with a new observation.
-    pub fn update(&mut self, pos: Vec2, vel: Vec2, snr: f64) {
-        // Below we will omit the H vector since our measurement model is 1:1
-        // with our measurement vector (i.e. it is the identity matrix).
-        let measurement_covariance = calculate_measurement_covariance(snr);
-        let kalman_gain =
-            self.state_covariance * add(self.state_covariance, &measurement_covariance).inverse();
-        let measurement = Vec4f::new(pos.x as f32, vel.x as f32, pos.y as f32, vel.y as f32);
-        self.state = self.state + kalman_gain * (measurement - self.state);
-        self.state_covariance = (add(Mat4f::identity(), &neg(kalman_gain))) * self.state_covariance;
+    // A coasting contact is deleted after this many consecutive failures to
+    // find it.
+    pub max_coast_misses: u32,
+
+    // Any contact is deleted if we haven't seen it for this many seconds.
+    pub max_coast_time: f64,
+}
+
+impl Default for TrackPolicy {
+    fn default() -> Self {
+        TrackPolicy {
+            confirm_hits: 2,
+            confirm_window: 3,
+            max_coast_misses: 2,
+            max_coast_time: 3.,
+        }
     }
 }
 
@@ -137,37 +774,90 @@ pub struct Contact {
     pub id: u32,
 
     // The physics of the contact.
-    filter: KalmanFilter,
-
-    // The velocity we saw last time we updated.
-    vel_last_update: Vec2,
-
-    // The estimated acceleration over the course of the last update window.
-    acc: Vec2,
+    filter: Imm,
 
     // When was the last time we saw this contact on the radar?
     last_seen_tick: u32,
 
+    // The squared Mahalanobis distance between the last scan we used to
+    // update this contact and where its best model expected the contact to
+    // be.
+    gate_statistic: f64,
+
+    // The normalized innovation squared (NIS) of the last NIS_WINDOW updates,
+    // averaged over the models. It follows a chi-square distribution
+    // with MEASUREMENT_DIM degrees of freedom if the filter is well tuned.
+    nis_history: VecDeque<f64>,
+
     // How many times have we tried to track this contact and failed to find it
     // where we expected. Resets every time we successfully track. If it climbs
     // too high we'll probably delete it from the contact database.
     tracking_miss_count: u32,
 
+    // Where the contact is in its lifecycle, and the rules for moving it
+    // along.
+    state: TrackState,
+    policy: TrackPolicy,
+
+    // One bit per attempt to track the contact, most recent in the lowest
+    // bit. Set if we found the contact.
+    hit_history: u32,
+    attempts: u32,
+
+    // The last HISTORY_LEN updates, oldest first.
+    history: VecDeque<HistoryEntry>,
+
     // The predicted position of the contact at a future time. Used to paint
     // where we were aiming.
     predictions: VecDeque<(f64, Vec2)>,
 }
 
 impl Contact {
-    pub fn new(class: Class, id: u32, pos: Vec2, vel: Vec2, snr: f64) -> Contact {
+    pub fn new(
+        class: Class,
+        id: u32,
+        pos: Vec2,
+        vel: Vec2,
+        snr: f64,
+        policy: TrackPolicy,
+    ) -> Contact {
+        let filter = Imm::new(
+            class,
+            &Observation {
+                origin: position(),
+                pos,
+                vel,
+                snr,
+            },
+        );
+        let mut history = VecDeque::with_capacity(HISTORY_LEN);
+        history.push_back(HistoryEntry {
+            tick: current_tick(),
+            scan: ScanResult {
+                class,
+                position: pos,
+                velocity: vel,
+                rssi: 0.,
+                snr,
+            },
+            prior_state: filter.state,
+            prior_covariance: filter.state_covariance,
+            state: filter.state,
+            state_covariance: filter.state_covariance,
+        });
         Contact {
             class,
             id,
-            filter: KalmanFilter::new(class, pos, vel, snr),
-            vel_last_update: vel,
-            acc: vec2(0., 0.),
+            filter,
             last_seen_tick: current_tick(),
+            gate_statistic: 0.,
+            nis_history: VecDeque::with_capacity(NIS_WINDOW),
             tracking_miss_count: 0,
+            state: TrackState::Tentative,
+            policy,
+            hit_history: 1,
+            attempts: 1,
+            history,
             predictions: VecDeque::with_capacity(5),
         }
     }
@@ -181,13 +871,13 @@ impl Contact {
     }
 
     pub fn pos(&self) -> Vec2 {
-        // Note: do not need to factor vel into pos because the model already
-        // does that.
-        self.filter.pos() + 0.5 * self.acc * self.since_update().powi(2)
+        // Note: the filter is predicted forward every tick, so it already
+        // accounts for the time since the last update.
+        self.filter.pos()
     }
 
     pub fn vel(&self) -> Vec2 {
-        self.filter.vel() + self.acc * self.since_update()
+        self.filter.vel()
     }
 
     pub fn class(&self) -> Class {
@@ -199,7 +889,14 @@ impl Contact {
     }
 
     pub fn acc(&self) -> Vec2 {
-        self.acc
+        self.filter.acc()
+    }
+
+    // The probability that the contact is currently maneuvering rather than
+    // coasting. Targets with a low maneuvering probability are predictable
+    // and worth shooting at.
+    pub fn maneuvering_probability(&self) -> f64 {
+        self.filter.maneuvering_probability()
     }
 
     // Records a prediction of where the contact will be at time t.
@@ -217,21 +914,79 @@ impl Contact {
     pub fn pos_stddev(&self) -> f64 {
         // The standard deviation of the position is the square root of the
         // variance. The variance is the sum of the variances in each axis.
-        let len_sq = self.filter.state_covariance[0] + self.filter.state_covariance[2];
-        if len_sq > 0. {
-            len_sq.sqrt() as f64
-        } else {
-            0.
+        let len_sq = self.filter.state_covariance[(0, 0)] + self.filter.state_covariance[(3, 3)];
+        if len_sq > 0. { len_sq.sqrt() } else { 0. }
+    }
+
+    // The covariance of the contact's position t seconds from now, assuming
+    // it keeps its current acceleration. This only propagates our current
+    // uncertainty: it doesn't account for the contact maneuvering in the
+    // meantime.
+    pub fn pos_covariance_after(&self, t: f64) -> Matrix<2, 2> {
+        let j = Matrix::<2, STATE_DIM>::from_rows([
+            [1., t, 0.5 * t * t, 0., 0., 0.],
+            [0., 0., 0., 1., t, 0.5 * t * t],
+        ]);
+        (j * self.filter.state_covariance * j.transpose()).symmetrized()
+    }
+
+    // Returns the squared Mahalanobis distance between the scan and where we
+    // expect this contact to be, accounting for both our uncertainty and the
+    // noise in the scan. Scans of a different class are infinitely far away.
+    pub fn gate_distance_sq(&self, scan: &ScanResult) -> f64 {
+        if scan.class != self.class {
+            return f64::INFINITY;
         }
+        self.filter
+            .distance_sq(&Observation::from_scan(scan))
+            .unwrap_or(f64::INFINITY)
     }
 
-    // Reports the furthest away a scan can be and still match this contact.
-    // For contacts we've scanned recently with good signal this will be
-    // approximately the assumed ship size. As time goes since our last scan
-    // the allowed range will increase.
-    pub fn max_distance_for_match(&self) -> f64 {
-        let assumed_ship_size = 50.0;
-        3. * self.pos_stddev() + assumed_ship_size
+    // Reports whether the scan is plausibly an observation of this contact.
+    pub fn gates(&self, scan: &ScanResult) -> bool {
+        self.gate_distance_sq(scan) < GATE_THRESHOLD
+    }
+
+    // Reports whether the two contacts are plausibly the same object.
+    pub fn is_duplicate_of(&self, other: &Contact) -> bool {
+        self.class == other.class
+            && self
+                .filter
+                .distance_sq_to(&other.filter)
+                .is_some_and(|d| d < GATE_THRESHOLD)
+    }
+
+    // The gate statistic of the last scan used to update this contact.
+    pub fn gate_statistic(&self) -> f64 {
+        self.gate_statistic
+    }
+
+    // The average normalized innovation squared over the last few updates.
+    // Should be close to MEASUREMENT_DIM for a well tuned filter. Returns None
+    // if we haven't updated the contact yet.
+    pub fn average_nis(&self) -> Option<f64> {
+        if self.nis_history.is_empty() {
+            return None;
+        }
+        Some(self.nis_history.iter().sum::<f64>() / self.nis_history.len() as f64)
+    }
+
+    // Judges whether the filter is over- or under-confident, using a 95%
+    // confidence interval on the average NIS. The sum of n chi-square samples
+    // with k degrees of freedom has mean nk and variance 2nk. Returns None if
+    // we haven't updated the contact yet.
+    pub fn nis_consistency(&self) -> Option<Consistency> {
+        let average = self.average_nis()?;
+        let k = MEASUREMENT_DIM as f64;
+        let n = self.nis_history.len() as f64;
+        let margin = 1.96 * (2. * k / n).sqrt();
+        Some(if average > k + margin {
+            Consistency::Overconfident
+        } else if average < k - margin {
+            Consistency::Underconfident
+        } else {
+            Consistency::Consistent
+        })
     }
 
     pub fn tick(&mut self) {
@@ -242,11 +997,31 @@ impl Contact {
 
     pub fn update(&mut self, scan: ScanResult) -> &Contact {
         // Update the filter with the new position and velocity.
-        self.filter.update(scan.position, scan.velocity, scan.snr);
-        self.acc = (self.vel() - self.vel_last_update) / self.since_update();
-        self.vel_last_update = self.vel();
+        self.gate_statistic = self.gate_distance_sq(&scan);
+        let obs = Observation::from_scan(&scan);
+        if let Some(nis) = self.filter.model_nis(&obs) {
+            if self.nis_history.len() == NIS_WINDOW {
+                self.nis_history.pop_front();
+            }
+            self.nis_history.push_back(nis);
+        }
+        let prior_state = self.filter.state;
+        let prior_covariance = self.filter.state_covariance;
+        self.filter.update(&obs);
+        if self.history.len() == HISTORY_LEN {
+            self.history.pop_front();
+        }
+        self.history.push_back(HistoryEntry {
+            tick: current_tick(),
+            scan,
+            prior_state,
+            prior_covariance,
+            state: self.filter.state,
+            state_covariance: self.filter.state_covariance,
+        });
         self.last_seen_tick = current_tick();
         self.tracking_miss_count = 0;
+        self.record_attempt(true);
         self
     }
 
@@ -255,15 +1030,89 @@ impl Contact {
     pub fn add_miss(&mut self) -> u32 {
         self.tracking_miss_count += 1;
         self.predictions.clear();
+        self.record_attempt(false);
         self.tracking_miss_count
     }
 
-    pub fn draw(&self) {
+    // The filtered estimate of the contact at each of its recent updates,
+    // oldest first. Each estimate only uses the scans up to that point.
+    pub fn history(&self) -> impl Iterator<Item = TrackPoint> + '_ {
+        self.history
+            .iter()
+            .map(|e| TrackPoint::new(e.tick, &e.state))
+    }
+
+    // The raw scans of the contact at each of its recent updates, oldest
+    // first.
+    pub fn scans(&self) -> impl Iterator<Item = &ScanResult> {
+        self.history.iter().map(|e| &e.scan)
+    }
+
+    // The smoothed estimate of the contact at each of its recent updates,
+    // oldest first. Unlike history(), every estimate uses all of the scans
+    // we have, which gives a much better reconstruction of how the contact
+    // has been moving.
+    pub fn smoothed_history(&self) -> Vec<TrackPoint> {
+        self.history
+            .iter()
+            .zip(rts_smooth(&self.history))
+            .map(|(e, (state, _))| TrackPoint::new(e.tick, &state))
+            .collect()
+    }
+
+    pub fn state(&self) -> TrackState {
+        self.state
+    }
+
+    // Reports whether the contact has been confirmed and not since deleted.
+    // Coasting contacts are still confirmed: we just haven't seen them for a
+    // little while.
+    pub fn is_confirmed(&self) -> bool {
+        matches!(self.state, TrackState::Confirmed | TrackState::Coasting)
+    }
+
+    // Advances the lifecycle after an attempt to track the contact.
+    fn record_attempt(&mut self, hit: bool) {
+        self.hit_history = (self.hit_history << 1) | hit as u32;
+        self.attempts += 1;
+        let window = self.policy.confirm_window.clamp(1, u32::BITS);
+        let window_mask = u32::MAX >> (u32::BITS - window);
+        let hits = (self.hit_history & window_mask).count_ones();
+        self.state = match (self.state, hit) {
+            (TrackState::Tentative, _) if hits >= self.policy.confirm_hits => TrackState::Confirmed,
+            // Once the window is full without enough hits the contact can no
+            // longer be confirmed. It was probably noise.
+            (TrackState::Tentative, _) if self.attempts >= window => TrackState::Deleted,
+            (TrackState::Tentative, _) => TrackState::Tentative,
+            (TrackState::Confirmed | TrackState::Coasting, true) => TrackState::Confirmed,
+            (TrackState::Confirmed | TrackState::Coasting, false)
+                if self.tracking_miss_count >= self.policy.max_coast_misses =>
+            {
+                TrackState::Deleted
+            }
+            (TrackState::Confirmed | TrackState::Coasting, false) => TrackState::Coasting,
+            (TrackState::Deleted, _) => TrackState::Deleted,
+        };
+    }
+
+    // Deletes the contact if we haven't seen it in too long.
+    fn expire(&mut self) {
+        if self.since_update() > self.policy.max_coast_time {
+            self.state = TrackState::Deleted;
+        }
+    }
+
+    pub fn draw(&self, options: &DrawOptions) {
         // Draw the contact.
         let mut pos = self.pos();
-        draw_diamond(pos, self.pos_stddev(), 0xff0000);
+        let color = match self.nis_consistency() {
+            Some(Consistency::Overconfident) if options.consistency => 0xff00ff,
+            Some(Consistency::Underconfident) if options.consistency => 0x0000ff,
+            _ => 0xff0000,
+        };
+        draw_diamond(pos, self.pos_stddev(), color);
         draw_line(pos, pos + self.vel(), 0xfff000);
-        draw_line(pos, pos + self.acc, 0xffff00);
+        draw_line(pos, pos + self.acc(), 0xffff00);
         pos += vec2(20., 0.); // Draw off to the right.
         let line_height = vec2(0., 15.);
         draw_text!(pos, 0x00ff00, "Age{:.2}", self.since_update());
@@ -274,27 +1123,67 @@ impl Contact {
             "Dev{:.0}m",
             self.pos_stddev()
         );
-        if let Some((t, pos)) = self.predictions.front() {
-            if *t >= current_time() {
-                draw_diamond(*pos, 20., 0x00ff00);
+        pos += line_height;
+        draw_text!(
+            pos + vec2(0., 20.),
+            0xff0000,
+            "Gate{:.1}",
+            self.gate_statistic
+        );
+        if options.consistency
+            && let Some(average) = self.average_nis()
+        {
+            pos += line_height;
+            draw_text!(pos + vec2(0., 20.), color, "NIS{:.1}", average);
+        }
+        if let Some((t, pos)) = self.predictions.front()
+            && *t >= current_time()
+        {
+            draw_diamond(*pos, 20., 0x00ff00);
+        }
+        if options.trail {
+            let trail = self.smoothed_history();
+            for (a, b) in trail.iter().zip(trail.iter().skip(1)) {
+                draw_line(a.pos, b.pos, 0x808080);
             }
-        };
+            if let Some(last) = trail.last() {
+                draw_line(last.pos, self.pos(), 0x808080);
+            }
+        }
     }
 }
 
 pub struct Contacts {
     contacts: HashMap<u32, Contact>,
     next_id: u32,
+    policy: TrackPolicy,
+    draw_options: DrawOptions,
+}
+
+impl Default for Contacts {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Contacts {
     pub fn new() -> Contacts {
+        Self::with_policy(TrackPolicy::default())
+    }
+
+    pub fn with_policy(policy: TrackPolicy) -> Contacts {
         Contacts {
             contacts: HashMap::new(),
             next_id: 0,
+            policy,
+            draw_options: DrawOptions::default(),
         }
     }
 
+    pub fn set_draw_options(&mut self, draw_options: DrawOptions) {
+        self.draw_options = draw_options;
+    }
+
     pub fn at(&self, index: u32) -> Option<&Contact> {
         self.contacts.get(&index)
     }
@@ -311,10 +1200,18 @@ impl Contacts {
         self.contacts.values_mut()
     }
 
+    // Iterates over the contacts we are confident are real.
+    pub fn confirmed(&self) -> impl Iterator<Item = &Contact> {
+        self.contacts.values().filter(|c| c.is_confirmed())
+    }
+
     pub fn tick(&mut self) {
         // Update all the contacts.
-        self.contacts.retain(|_, c| c.tracking_miss_count < 2);
-        for (_, contact) in &mut self.contacts {
+        for contact in self.contacts.values_mut() {
+            contact.expire();
+        }
+        self.contacts.retain(|_, c| c.state != TrackState::Deleted);
+        for contact in self.contacts.values_mut() {
             contact.tick();
         }
     }
@@ -328,43 +1225,32 @@ impl Contacts {
     }
 
     pub fn update(&mut self, index: u32, scan_result: Option<ScanResult>) -> Option<&Contact> {
-        if let None = scan_result {
+        if scan_result.is_none() {
             self.at_mut(index)?.add_miss();
             return None;
         }
 
-        let is_dup: bool;
+        let scan_result = scan_result.unwrap();
         {
-            let scan_result = scan_result.unwrap();
-            let dev_after: f64;
-            let pos: Vec2;
-            {
-                let c = self.at_mut(index)?;
-                c.update(scan_result.clone());
-                dev_after = c.pos_stddev();
-                pos = c.pos();
-            }
-
-            if dev_after < 50. {
-                // Once we've resolve the position of the contact to a sufficient
-                // degree of accuracy, check if it's the same as any contact with
-                // a lower index. If it is, discard it.
-                is_dup = self
-                    .contacts
-                    .values()
-                    .filter(|co| {
-                        co.id < index
-                            && co.class == scan_result.class
-                            && (co.pos() - pos).length() < co.max_distance_for_match()
-                    })
-                    .next()
-                    .is_some();
-                debug!("dev after < 50 is_dup: {}", is_dup);
-            } else {
-                debug!("dev after: {}", dev_after);
-                is_dup = false;
+            let c = self.at_mut(index)?;
+            if !c.gates(&scan_result) {
+                // Whatever we found, it wasn't this contact. It might be
+                // something new.
+                debug!("scan outside gate: {:.1}", c.gate_distance_sq(&scan_result));
+                c.add_miss();
+                self.recv_contact(scan_result);
+                return None;
             }
+            c.update(scan_result);
         }
+
+        // Check if the updated contact is the same as any contact with a
+        // lower index. If it is, discard it.
+        let c = self.at(index)?;
+        let is_dup = self
+            .contacts
+            .values()
+            .any(|co| co.id < index && c.is_duplicate_of(co));
         if is_dup {
             // We have a duplicate contact. Remove the one with the higher id.
             self.contacts.remove(&index);
@@ -375,20 +1261,51 @@ impl Contacts {
     }
 
     pub fn recv_contact(&mut self, scan_result: ScanResult) {
-        // Find the contact that is most likely to be the same contact as the
-        // scan result. It needs to have the same class and be within the
-        // volume we consider 99% likely to contain the contact (3 std dev).
-        let dist_from_scan = |a: &Contact| (a.pos() - scan_result.position).length() as f64;
-        if let Some(_) = self
-            .contacts
-            .values()
-            .filter(|c| c.class == scan_result.class)
-            .min_by_key(|a| dist_from_scan(a) as i32)
-            .filter(|a| dist_from_scan(a) < a.max_distance_for_match())
+        self.recv_contacts(vec![scan_result]);
+    }
+
+    // Associates a batch of scan results with the existing contacts. Each
+    // contact is updated with at most one scan and each scan updates at most
+    // one contact, choosing the pairing that minimizes the total Mahalanobis
+    // distance. Scans that aren't paired with a contact whose gate they fall
+    // within become new contacts.
+    pub fn recv_contacts(&mut self, scan_results: Vec<ScanResult>) {
+        // Pairings outside of the gate are forbidden. The assignment solver
+        // needs finite costs, so make them expensive enough that it will never
+        // prefer one over a pairing inside the gate.
+        let forbidden_cost = GATE_THRESHOLD * (scan_results.len() + 1) as f64;
+        let mut ids: Vec<u32> = self.contacts.keys().copied().collect();
+        ids.sort();
+        let costs: Vec<Vec<f64>> = ids
+            .iter()
+            .map(|id| {
+                let c = &self.contacts[id];
+                scan_results
+                    .iter()
+                    .map(|s| c.gate_distance_sq(s).min(forbidden_cost))
+                    .collect()
+            })
+            .collect();
+
+        let mut assigned = vec![false; scan_results.len()];
+        for ((id, scan_index), row) in ids.iter().zip(hungarian(&costs)).zip(&costs) {
+            let Some(scan_index) = scan_index else {
+                continue;
+            };
+            if row[scan_index] >= GATE_THRESHOLD {
+                continue;
+            }
+            assigned[scan_index] = true;
+            if let Some(c) = self.contacts.get_mut(id) {
+                c.update(scan_results[scan_index].clone());
+            }
+        }
+
+        for (scan_result, _) in scan_results
+            .into_iter()
+            .zip(assigned)
+            .filter(|(_, assigned)| !assigned)
         {
-            // Ignore any contacts that can be confused for something already
-            // in the database.
-        } else {
             self.contacts.insert(
                 self.next_id,
                 Contact::new(
@@ -397,22 +1314,470 @@ impl Contacts {
                     scan_result.position,
                     scan_result.velocity,
                     scan_result.snr,
+                    self.policy,
                 ),
             );
             self.next_id += 1;
         }
     }
 
-    fn cleanup(&mut self) {
-        // Remove contacts if we've failed to track them twice.
-        for contact in self.contacts.values_mut() {
-            contact.clean_old_predictions();
+    pub fn draw(&self) {
+        for contact in self.contacts.values() {
+            contact.draw(&self.draw_options);
         }
     }
+}
 
-    pub fn draw(&self) {
-        for contact in self.contacts.values() {
-            contact.draw();
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_env::*;
+    use oort_api::prelude::oorandom::Rand64;
+
+    fn fighter_at(pos: Vec2, vel: Vec2) -> ScanResult {
+        scan_result(Class::Fighter, pos, vel)
+    }
+
+    #[test]
+    fn new_scan_creates_tentative_contact() {
+        let _env = Env::new(Class::Fighter);
+        let mut contacts = Contacts::new();
+        contacts.recv_contact(fighter_at(vec2(1000., 0.), vec2(0., 0.)));
+        let c = contacts.iter().next().unwrap();
+        assert_eq!(c.state(), TrackState::Tentative);
+        assert_eq!(contacts.confirmed().count(), 0);
+    }
+
+    #[test]
+    fn rescanned_contact_is_confirmed() {
+        let mut env = Env::new(Class::Fighter);
+        let mut contacts = Contacts::new();
+        let vel = vec2(100., 0.);
+        contacts.recv_contact(fighter_at(vec2(1000., 0.), vel));
+        for _ in 0..10 {
+            env.advance_ticks(1);
+            contacts.tick();
+        }
+        let pos = vec2(1000., 0.) + vel * 10. * TICK_LENGTH;
+        let c = contacts.update(0, Some(fighter_at(pos, vel))).unwrap();
+        assert_eq!(c.state(), TrackState::Confirmed);
+        assert_eq!(contacts.confirmed().count(), 1);
+    }
+
+    #[test]
+    fn missed_contact_coasts_then_is_deleted() {
+        let mut env = Env::new(Class::Fighter);
+        let mut contacts = Contacts::new();
+        contacts.recv_contact(fighter_at(vec2(1000., 0.), vec2(0., 0.)));
+        env.advance_ticks(10);
+        contacts.tick();
+        contacts.update(0, Some(fighter_at(vec2(1000., 0.), vec2(0., 0.))));
+
+        contacts.update(0, None);
+        assert_eq!(contacts.at(0).unwrap().state(), TrackState::Coasting);
+        assert_eq!(contacts.confirmed().count(), 1);
+
+        contacts.update(0, None);
+        contacts.tick();
+        assert!(contacts.at(0).is_none());
+    }
+
+    #[test]
+    fn contact_not_seen_for_too_long_is_deleted() {
+        let mut env = Env::new(Class::Fighter);
+        let mut contacts = Contacts::new();
+        contacts.recv_contact(fighter_at(vec2(1000., 0.), vec2(0., 0.)));
+        env.advance_ticks((TrackPolicy::default().max_coast_time / TICK_LENGTH) as u32 + 1);
+        contacts.tick();
+        assert_eq!(contacts.iter().count(), 0);
+    }
+
+    #[test]
+    fn separate_returns_create_separate_contacts() {
+        let _env = Env::new(Class::Fighter);
+        let mut contacts = Contacts::new();
+        contacts.recv_contacts(vec![
+            fighter_at(vec2(1000., 0.), vec2(0., 0.)),
+            fighter_at(vec2(0., 1000.), vec2(0., 0.)),
+        ]);
+        assert_eq!(contacts.iter().count(), 2);
+    }
+
+    #[test]
+    fn matching_return_updates_existing_contact() {
+        let mut env = Env::new(Class::Fighter);
+        let mut contacts = Contacts::new();
+        contacts.recv_contact(fighter_at(vec2(1000., 0.), vec2(0., 0.)));
+        env.advance_ticks(10);
+        contacts.tick();
+        // One return is the existing contact. The other, close by, is
+        // something new and must not be merged into it.
+        contacts.recv_contacts(vec![
+            fighter_at(vec2(1300., 0.), vec2(0., 0.)),
+            fighter_at(vec2(1000., 1.), vec2(0., 0.)),
+        ]);
+        assert_eq!(contacts.iter().count(), 2);
+        let c = contacts.at(0).unwrap();
+        assert_eq!(c.since_update_ticks(), 0);
+        assert!((c.pos() - vec2(1000., 0.)).length() < 5., "{:?}", c.pos());
+    }
+
+    #[test]
+    fn different_class_is_not_associated() {
+        let _env = Env::new(Class::Fighter);
+        let mut contacts = Contacts::new();
+        contacts.recv_contact(fighter_at(vec2(1000., 0.), vec2(0., 0.)));
+        contacts.recv_contact(scan_result(Class::Missile, vec2(1000., 0.), vec2(0., 0.)));
+        assert_eq!(contacts.iter().count(), 2);
+    }
+
+    #[test]
+    fn tracks_accelerating_target() {
+        let mut env = Env::new(Class::Fighter);
+        let mut contacts = Contacts::new();
+        let acc = vec2(0., 30.);
+        let mut pos = vec2(3000., 0.);
+        let mut vel = vec2(-100., 0.);
+        contacts.recv_contact(fighter_at(pos, vel));
+        for tick in 1..=300 {
+            env.advance_ticks(1);
+            pos += vel * TICK_LENGTH + 0.5 * acc * TICK_LENGTH.powi(2);
+            vel += acc * TICK_LENGTH;
+            contacts.tick();
+            if tick % 8 == 0 {
+                contacts.update(0, Some(fighter_at(pos, vel)));
+            }
+        }
+        let c = contacts.at(0).unwrap();
+        assert!((c.pos() - pos).length() < 5., "{:?} {:?}", c.pos(), pos);
+        assert!((c.vel() - vel).length() < 1., "{:?} {:?}", c.vel(), vel);
+        assert!((c.acc() - acc).length() < 5., "{:?}", c.acc());
+        assert!(c.maneuvering_probability() > 0.5);
+    }
+
+    #[test]
+    fn keeps_track_through_sudden_burn() {
+        // A coasting fighter that starts burning flat out between two scans
+        // is still the same contact, not a new one.
+        for acc in [vec2(0., 60.), vec2(60., 0.)] {
+            let mut env = Env::new(Class::Fighter);
+            let mut contacts = Contacts::new();
+            let mut pos = vec2(3000., 0.);
+            let mut vel = vec2(-100., 0.);
+            contacts.recv_contact(fighter_at(pos, vel));
+            for tick in 1..=240 {
+                let acc = if tick > 120 { acc } else { vec2(0., 0.) };
+                env.advance_ticks(1);
+                pos += vel * TICK_LENGTH + 0.5 * acc * TICK_LENGTH.powi(2);
+                vel += acc * TICK_LENGTH;
+                contacts.tick();
+                if tick % 8 == 0 {
+                    let updated = contacts.update(0, Some(fighter_at(pos, vel)));
+                    assert!(updated.is_some(), "lost at tick {} with {:?}", tick, acc);
+                    assert_eq!(contacts.iter().count(), 1);
+                }
+            }
+            let c = contacts.at(0).unwrap();
+            assert!((c.pos() - pos).length() < 5., "{:?} {:?}", c.pos(), pos);
+            assert!(c.maneuvering_probability() > 0.5);
+        }
+    }
+
+    #[test]
+    fn gate_rejects_scan_with_wrong_velocity() {
+        // Where we expect the contact to be, but not going where we expect.
+        let mut env = Env::new(Class::Fighter);
+        let mut contacts = Contacts::new();
+        let pos = vec2(3000., 0.);
+        let vel = vec2(-100., 0.);
+        contacts.recv_contact(fighter_at(pos, vel));
+        for tick in 1..=60 {
+            env.advance_ticks(1);
+            contacts.tick();
+            if tick % 8 == 0 {
+                contacts.update(0, Some(fighter_at(pos + vel * current_time(), vel)));
+            }
+        }
+        env.advance_ticks(1);
+        contacts.tick();
+        let c = contacts.at(0).unwrap();
+        let expected = pos + vel * current_time();
+        assert!(c.gates(&fighter_at(expected, vel)));
+        assert!(!c.gates(&fighter_at(expected, vec2(100., 0.))));
+        assert!(!c.gates(&fighter_at(expected, vel + vec2(0., 50.))));
+    }
+
+    #[test]
+    fn measurement_noise_is_stretched_across_the_beam() {
+        let obs = |pos| observation(pos, vec2(0., 0.));
+        // Along the x axis: x is along the beam and y across it.
+        let far = cartesian_measurement_covariance(&obs(vec2(3000., 0.)));
+        assert!(far[(2, 2)] > 4. * far[(0, 0)], "{:?}", far);
+        let near = cartesian_measurement_covariance(&obs(vec2(500., 0.)));
+        assert!(near[(2, 2)] < near[(0, 0)], "{:?}", near);
+    }
+
+    #[test]
+    fn process_noise_grows_with_surprising_innovations() {
+        let mut noise = AdaptiveProcessNoise::default();
+        noise.observe(40., MEASUREMENT_DIM);
+        assert!(noise.scale() > 1.5, "{}", noise.scale());
+        for _ in 0..50 {
+            noise.observe(1e6, MEASUREMENT_DIM);
+        }
+        assert_eq!(noise.scale(), 20.);
+        noise.observe(f64::NAN, MEASUREMENT_DIM);
+        assert_eq!(noise.scale(), 20.);
+    }
+
+    #[test]
+    fn process_noise_shrinks_only_to_the_floor() {
+        let mut noise = AdaptiveProcessNoise::default();
+        // Innovations of the expected size leave it alone.
+        for _ in 0..10 {
+            noise.observe(MEASUREMENT_DIM as f64, MEASUREMENT_DIM);
+        }
+        assert!((noise.scale() - 1.).abs() < 1e-9, "{}", noise.scale());
+        noise.observe(1., MEASUREMENT_DIM);
+        assert!(noise.scale() < 1.);
+        for _ in 0..100 {
+            noise.observe(0., MEASUREMENT_DIM);
+        }
+        assert_eq!(noise.scale(), 0.5);
+    }
+
+    #[test]
+    fn burn_inflates_only_the_maneuvering_model() {
+        let mut env = Env::new(Class::Fighter);
+        let mut contacts = Contacts::new();
+        let mut pos = vec2(3000., 0.);
+        let mut vel = vec2(-100., 0.);
+        contacts.recv_contact(fighter_at(pos, vel));
+        let scales = |contacts: &Contacts| {
+            let filters = &contacts.at(0).unwrap().filter.filters;
+            filters.each_ref().map(|f| f.process_noise_scale)
+        };
+        for tick in 1..=160 {
+            let acc = if tick > 120 { vec2(0., 60.) } else { vec2(0., 0.) };
+            env.advance_ticks(1);
+            pos += vel * TICK_LENGTH + 0.5 * acc * TICK_LENGTH.powi(2);
+            vel += acc * TICK_LENGTH;
+            contacts.tick();
+            if tick % 8 == 0 {
+                contacts.update(0, Some(fighter_at(pos, vel)));
+            }
+            if tick == 120 {
+                // Perfect scans of a coasting target shrink the noise, but
+                // not all the way.
+                assert_eq!(scales(&contacts), [1., 0.5]);
+            }
+        }
+        let [cv, ca] = scales(&contacts);
+        assert_eq!(cv, 1.);
+        assert!(ca > 2., "{}", ca);
+    }
+
+    // A standard normal sample, using the Box-Muller transform.
+    fn gaussian(rng: &mut Rand64) -> f64 {
+        let u1 = 1. - rng.rand_float();
+        let u2 = rng.rand_float();
+        (-2. * u1.ln()).sqrt() * (2. * PI * u2).cos()
+    }
+
+    // A scan of a fighter with noise drawn from the tracker's own measurement
+    // model.
+    fn noisy_fighter_at(rng: &mut Rand64, pos: Vec2, vel: Vec2) -> ScanResult {
+        let snr = 30.;
+        let r = calculate_measurement_covariance(snr);
+        let rel = pos - position();
+        let range = rel.length() + gaussian(rng) * r[(0, 0)].sqrt();
+        let bearing = rel.angle() + gaussian(rng) * r[(1, 1)].sqrt();
+        let noise = vec2(gaussian(rng), gaussian(rng)) * r[(2, 2)].sqrt();
+        ScanResult {
+            class: Class::Fighter,
+            position: position() + vec2(range, 0.).rotate(bearing),
+            velocity: vel + noise,
+            rssi: 0.,
+            snr,
+        }
+    }
+
+    #[test]
+    fn nis_averages_measurement_dim_on_consistent_target() {
+        let mut env = Env::new(Class::Fighter);
+        let mut rng = Rand64::new(1);
+        let mut contacts = Contacts::new();
+        let mut pos = vec2(5000., 0.);
+        let mut vel = vec2(-50., 20.);
+        contacts.recv_contact(noisy_fighter_at(&mut rng, pos, vel));
+        let mut nis = Vec::new();
+        for tick in 1..=2400 {
+            // The small random corrections the constant velocity model
+            // expects of a coasting ship.
+            let acc = vec2(gaussian(&mut rng), gaussian(&mut rng)) * 60. * 0.05;
+            env.advance_ticks(1);
+            pos += vel * TICK_LENGTH + 0.5 * acc * TICK_LENGTH.powi(2);
+            vel += acc * TICK_LENGTH;
+            contacts.tick();
+            if tick % 8 == 0 {
+                // Update directly rather than through the gate, which turns
+                // away 1% of scans of a perfectly consistent track.
+                let c = contacts.at_mut(0).unwrap();
+                c.update(noisy_fighter_at(&mut rng, pos, vel));
+                nis.push(*c.nis_history.back().unwrap());
+            }
+        }
+        // The maneuvering model's share of the average is diffuse while the
+        // contact coasts, so it comes out a bit under 4.
+        let average = nis.iter().sum::<f64>() / nis.len() as f64;
+        assert!((average - MEASUREMENT_DIM as f64).abs() < 0.6, "{}", average);
+        assert_ne!(
+            contacts.at(0).unwrap().nis_consistency(),
+            Some(Consistency::Overconfident)
+        );
+    }
+
+    #[test]
+    fn maneuvering_probability_follows_burns() {
+        let mut env = Env::new(Class::Fighter);
+        let mut rng = Rand64::new(1);
+        let mut contacts = Contacts::new();
+        let mut pos = vec2(3000., 0.);
+        let mut vel = vec2(-100., 0.);
+        contacts.recv_contact(noisy_fighter_at(&mut rng, pos, vel));
+        // Coast for 3 s, burn for 3 s, then coast again.
+        for tick in 1..=(9. / TICK_LENGTH) as u32 {
+            let t = tick as f64 * TICK_LENGTH;
+            let acc = if t > 3. && t <= 6. { vec2(0., 30.) } else { vec2(0., 0.) };
+            env.advance_ticks(1);
+            pos += vel * TICK_LENGTH + 0.5 * acc * TICK_LENGTH.powi(2);
+            vel += acc * TICK_LENGTH;
+            contacts.tick();
+            if tick % 8 == 0 {
+                let c = contacts.at_mut(0).unwrap();
+                c.update(noisy_fighter_at(&mut rng, pos, vel));
+                // Give the models half a second to settle after each change.
+                let p = c.maneuvering_probability();
+                let settled = |start: f64, end: f64| t > start + 0.5 && t <= end;
+                if settled(0., 3.) || settled(6., 9.) {
+                    assert!(p < 0.2, "{} at {}", p, t);
+                } else if settled(3., 6.) {
+                    assert!(p > 0.8, "{} at {}", p, t);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn smoothing_reduces_uncertainty_and_error() {
+        let mut env = Env::new(Class::Fighter);
+        let mut rng = Rand64::new(1);
+        let mut contacts = Contacts::new();
+        let mut pos = vec2(4000., 0.);
+        let vel = vec2(-50., 80.);
+        contacts.recv_contact(noisy_fighter_at(&mut rng, pos, vel));
+        let mut truth = vec![pos];
+        for tick in 1..=(HISTORY_LEN as u32 - 1) * 8 {
+            env.advance_ticks(1);
+            pos += vel * TICK_LENGTH;
+            contacts.tick();
+            if tick % 8 == 0 {
+                contacts
+                    .at_mut(0)
+                    .unwrap()
+                    .update(noisy_fighter_at(&mut rng, pos, vel));
+                truth.push(pos);
+            }
+        }
+        let c = contacts.at(0).unwrap();
+        let smoothed = rts_smooth(&c.history);
+        assert_eq!(smoothed.len(), truth.len());
+        let pos_variance = |p: &StateMatrix| p[(0, 0)] + p[(3, 3)];
+        let (mut filtered_error, mut smoothed_error) = (0., 0.);
+        for (i, (entry, (state, covariance))) in c.history.iter().zip(&smoothed).enumerate() {
+            let filtered = pos_variance(&entry.state_covariance);
+            let smoothed = pos_variance(covariance);
+            assert!(smoothed <= filtered * (1. + 1e-9), "{} {} at {}", smoothed, filtered, i);
+            if i + 1 < truth.len() {
+                assert!(smoothed < filtered, "{} {} at {}", smoothed, filtered, i);
+            }
+            filtered_error += (state_pos(&entry.state) - truth[i]).length().powi(2);
+            smoothed_error += (state_pos(state) - truth[i]).length().powi(2);
+        }
+        assert!(smoothed_error < filtered_error, "{} {}", smoothed_error, filtered_error);
+    }
+
+    fn observation(pos: Vec2, vel: Vec2) -> Observation {
+        Observation {
+            origin: vec2(0., 0.),
+            pos,
+            vel,
+            snr: 30.,
+        }
+    }
+
+    #[test]
+    fn measurement_jacobian_matches_finite_differences() {
+        let state = State::new([1500., -40., 3., 800., 25., -2.]);
+        let origin = vec2(100., -200.);
+        let (predicted, h) = predict_measurement(&state, origin);
+        let eps = 1e-4;
+        for i in 0..STATE_DIM {
+            let mut nudged = state;
+            nudged[i] += eps;
+            let (m, _) = predict_measurement(&nudged, origin);
+            for row in 0..MEASUREMENT_DIM {
+                let numeric = (m[row] - predicted[row]) / eps;
+                assert!((numeric - h[(row, i)]).abs() < 1e-6, "{} {}", row, i);
+            }
+        }
+    }
+
+    #[test]
+    fn ekf_update_pulls_estimate_toward_observation() {
+        let mut filter = KalmanFilter::new(
+            Class::Fighter,
+            MotionModel::ConstantVelocity,
+            &observation(vec2(2000., 0.), vec2(0., 0.)),
+        );
+        for _ in 0..8 {
+            filter.predict();
         }
+        let prior = filter.state_covariance;
+        let expected = filter.clone();
+
+        // A few meters across the beam from where we expected it: a bearing
+        // error, which is the bigger one at this range.
+        let surprising = observation(vec2(2000., 5.), vec2(0., 0.));
+        let ll = filter.update(&surprising).unwrap();
+        let pos = state_pos(&filter.state);
+        assert!(pos.y > 0. && pos.y < 5., "{:?}", pos);
+        assert!((pos.x - 2000.).abs() < 0.1, "{:?}", pos);
+        for i in 0..STATE_DIM {
+            assert!(filter.state_covariance[(i, i)] <= prior[(i, i)]);
+        }
+        assert!(filter.state_covariance[(3, 3)] < prior[(3, 3)]);
+        assert_eq!(filter.state_covariance, filter.state_covariance.transpose());
+
+        // An observation right where we expected it is more likely.
+        let mut unsurprised = expected;
+        let best = unsurprised
+            .update(&observation(vec2(2000., 0.), vec2(0., 0.)))
+            .unwrap();
+        assert!(best > ll);
+        assert_eq!(state_pos(&unsurprised.state), vec2(2000., 0.));
+    }
+
+    #[test]
+    fn position_uncertainty_grows_with_time() {
+        let _env = Env::new(Class::Fighter);
+        let mut contacts = Contacts::new();
+        contacts.recv_contact(fighter_at(vec2(2000., 0.), vec2(0., 100.)));
+        let c = contacts.at(0).unwrap();
+        let now = c.pos_covariance_after(0.);
+        assert!((now.trace().sqrt() - c.pos_stddev()).abs() < 1e-9);
+        let later = c.pos_covariance_after(2.);
+        assert!(later.trace() > now.trace());
+        assert!(later.determinant() >= 0.);
     }
 }
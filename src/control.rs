@@ -1,3 +1,9 @@
+use crate::{
+    fire_control::FireControl,
+    matrix::Matrix,
+    radar::ship_dim,
+    roots::{cubic, quartic},
+};
 use maths_rs::*;
 use oort_api::prelude::*;
 
@@ -16,6 +22,12 @@ fn to_unit(h: f64) -> Vec2 {
     Vec2::new(h.cos(), h.sin())
 }
 
+impl Default for Helm {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl Helm {
     pub fn new() -> Helm {
         Helm {
@@ -37,11 +49,22 @@ impl Helm {
     // the expected change in heading during the time it takes us to actually
     // perform the turn.
     pub fn turn(&mut self, ht: f64) -> f64 {
+        // How fast the desired heading is changing, if we were also asked to
+        // turn last tick.
+        let tick = current_tick();
+        let rate = if tick == self.last_heading_tick + 1 {
+            angle_diff(self.last_heading, ht) / TICK_LENGTH
+        } else {
+            0.
+        };
+        self.last_heading = ht;
+        self.last_heading_tick = tick;
+
         {
             // Try to make it so that next turn we are facing exactly at the
             // desired heading.
             let hn = heading() + TICK_LENGTH * angular_velocity();
-            let diff = ht - hn;
+            let diff = ht + rate * TICK_LENGTH - hn;
 
             if abs(diff) < TICK_LENGTH.powi(2) * max_angular_acceleration() {
                 // The angular acceleration should be exactly the same as the
@@ -78,6 +101,9 @@ impl Helm {
         // Nevertheless it is possible for both solutions to be negative if 
         // the starting velocity is high, acceleration and error are both low.
         // In this case, we just decelerate.
+        //
+        // If the desired heading is moving we work relative to it, so that we
+        // arrive turning at the same rate it is.
 
         let s = angle_diff(ht, heading());
         let a = if s < 0. {
@@ -85,16 +111,14 @@ impl Helm {
         } else {
             -max_angular_acceleration()
         };
-        let v = angular_velocity();
+        let v = angular_velocity() - rate;
         
         let sqrt_term = sqrt(-4. * a * s + 2. * v * v) / 2.;
         let t1 = (-v - sqrt_term) / a;
         let t2 = (-v + sqrt_term) / a;
         let t = if t1 < 0. {
             t2
-        } else if t2 < 0. {
-            t1
-        } else if abs(t1) < abs(t2) {
+        } else if t2 < 0. || abs(t1) < abs(t2) {
             t1
         } else {
             t2
@@ -152,7 +176,7 @@ pub fn position_next() -> Vec2 {
 // x0: The initial guess for the root.
 // tol: The tolerance for the root. If None, uses the default tolerance.
 // range: The range of x values to search. If we find a flat spot in the
-//    function we will resume the search from a random value in this range.
+//    function we will resume the search from another value in this range.
 //    Defaults to 0, 2*x0.
 fn newtons_method<T: (Fn(f64) -> Option<f64>), U: (Fn(f64) -> f64)>(
     f: &T,
@@ -162,6 +186,7 @@ fn newtons_method<T: (Fn(f64) -> Option<f64>), U: (Fn(f64) -> f64)>(
     range: Option<(f64, f64)>,
 ) -> Option<f64> {
     let mut x = x0;
+    let mut restarts = 0;
 
     for _ in 0..100 {
         if let Some(fx) = f(x) {
@@ -177,25 +202,56 @@ fn newtons_method<T: (Fn(f64) -> Option<f64>), U: (Fn(f64) -> f64)>(
         }
 
         // If f reports None, or if we are in a flat spot (dfx <= tol), then
-        // we randomly select a value in the range and try again.
+        // we restart from another value in the range. The restarts step
+        // through the range by the golden ratio, which spreads them out
+        // evenly without repeating and keeps the result deterministic.
         let rrange = range.unwrap_or((0., 2. * x0));
-        x = rand(rrange.0, rrange.1);
-        continue;
+        restarts += 1;
+        let u = (restarts as f64 * 0.618_033_988_749_895).fract();
+        x = rrange.0 + u * (rrange.1 - rrange.0);
     }
     None
 }
 
-// Given a bullet's speed and the current turn position, velocity, and
-// acceleration of an entity, returns the earliest position where a bullet can 
-// intersect with the trajectory of that entity if fired *next turn*. This 
-// function takes into account our current position and velocity but does not
-// take into account our acceleration.
-pub fn lead(e_pos: Vec2, e_vel: Vec2, e_acc: Vec2, b_spd: f64) -> Option<Vec2> {
-    let pos_next = position() + velocity() * TICK_LENGTH;
+// Returns the earliest time t > 0 at which a bullet fired now at speed b_spd
+// can meet an entity at relative position es, moving with relative velocity ev
+// and accelerating at ea, or None if it can't.
+//
+// The bullet can be at any point at distance b_spd * t from us, so we're
+// looking for the roots of
+//   |es + ev t + ea t^2 / 2|^2 - (b_spd t)^2
+// which is a quartic in t, or a quadratic if the entity isn't accelerating.
+// The roots are found in closed form. Should that fail to produce a usable
+// root, e.g. because of rounding in a nearly degenerate case, we fall back to
+// Newton's method.
+pub fn intercept_time(es: Vec2, ev: Vec2, ea: Vec2, b_spd: f64) -> Option<f64> {
+    let distance = es.length();
+    if distance == 0. {
+        return Some(0.);
+    }
+
+    // Solve for t in units of the time the bullet takes to cover the current
+    // distance, which keeps the coefficients of similar magnitude.
+    let t0 = distance / b_spd;
+    let ha = ea * 0.5;
+    let coeffs = [
+        ha.dot(ha) * t0.powi(4),
+        2. * ha.dot(ev) * t0.powi(3),
+        (ev.dot(ev) + 2. * ha.dot(es) - b_spd * b_spd) * t0 * t0,
+        2. * es.dot(ev) * t0,
+        es.dot(es),
+    ]
+    .map(|c| c / (distance * distance));
 
-    let es = e_pos - pos_next;
-    let ev = e_vel - velocity();
-    let ea = e_acc;
+    let miss = |t: f64| (es + t * (ev + t * ha)).length() - t * b_spd;
+    let tol = TICK_LENGTH / 10. * b_spd;
+    let analytic = quartic(coeffs[0], coeffs[1], coeffs[2], coeffs[3], coeffs[4])
+        .into_iter()
+        .map(|tau| tau * t0)
+        .find(|&t| t > 0. && miss(t).abs() < tol);
+    if analytic.is_some() {
+        return analytic;
+    }
 
     // Zero at times when the bullet distance from the ship and the enemy
     // distance from the ship are the same.
@@ -203,23 +259,424 @@ pub fn lead(e_pos: Vec2, e_vel: Vec2, e_acc: Vec2, b_spd: f64) -> Option<Vec2> {
         if t <= 0. {
             return None;
         }
-        Some(t * b_spd - (es + t * (ev + t * 0.5 * ea)).length())
+        Some(-miss(t))
     };
 
     // The derivative of the above function.
-    let fp = |t: f64| {
-        b_spd - (ea * t + ev).length()
-    };
+    let fp = |t: f64| b_spd - (ea * t + ev).length();
+
+    newtons_method(&f, &fp, t0, Some(TICK_LENGTH / 10.), None)
+}
+
+// Given a bullet's speed and the current turn position, velocity, and
+// acceleration of an entity, returns the earliest position where a bullet can
+// intersect with the trajectory of that entity if fired *next turn*. This
+// function takes into account our current position and velocity but does not
+// take into account our acceleration.
+pub fn lead(e_pos: Vec2, e_vel: Vec2, e_acc: Vec2, b_spd: f64) -> Option<Vec2> {
+    let pos_next = position() + velocity() * TICK_LENGTH;
+    intercept_time(e_pos - pos_next, e_vel - velocity(), e_acc, b_spd)
+        .map(|t: f64| pos_after(e_pos, e_vel, e_acc, t))
+}
+
+// One of our guns.
+#[derive(Clone, Copy, Debug)]
+pub struct Gun {
+    // The speed of its bullets relative to the ship. Bullets also inherit the
+    // ship's velocity.
+    pub speed: f64,
+    // Where bullets are spawned relative to the ship's center. For fixed guns
+    // x is along the direction the gun points, for turrets it's along the
+    // ship's heading.
+    pub offset: Vec2,
+    // How long a bullet flies before it disappears, in seconds.
+    pub lifetime: f64,
+    // Turrets can be aimed independently of the ship with aim(). Other guns
+    // fire along the ship's heading.
+    pub turret: bool,
+}
+
+// A fighter's forward gun.
+pub const FIGHTER_GUN: Gun = Gun {
+    speed: 1000.,
+    offset: Vec2 { x: 20., y: 0. },
+    lifetime: 5.,
+    turret: false,
+};
+
+// A frigate's forward main gun.
+pub const FRIGATE_MAIN_GUN: Gun = Gun {
+    speed: 4000.,
+    offset: Vec2 { x: 40., y: 0. },
+    lifetime: 2.,
+    turret: false,
+};
+
+// The forward gun of the given class, if it has one.
+pub fn main_gun(class: Class) -> Option<Gun> {
+    match class {
+        Class::Fighter => Some(FIGHTER_GUN),
+        Class::Frigate => Some(FRIGATE_MAIN_GUN),
+        _ => None,
+    }
+}
+
+// Our acceleration as commanded so far this tick, in world coordinates.
+pub fn commanded_acceleration() -> Vec2 {
+    vec2(
+        oort_api::sys::read_system_state(oort_api::SystemState::AccelerateX),
+        oort_api::sys::read_system_state(oort_api::SystemState::AccelerateY),
+    )
+    .rotate(heading())
+}
+
+// How to hit an entity with a bullet fired from a gun next tick.
+//
+// Unlike lead(), this accounts for the acceleration we have commanded this
+// tick, for where on the ship the bullet spawns and for the velocity it
+// inherits from us, so it stays accurate while we strafe.
+#[derive(Clone, Copy, Debug)]
+pub struct FiringSolution {
+    // The heading the gun needs to point at.
+    pub heading: f64,
+    // How long after it's fired the bullet meets the entity.
+    pub time_of_flight: f64,
+    // Where the bullet meets the entity.
+    pub aim_point: Vec2,
+    // How close to the entity a bullet fired along the heading we'll have
+    // next tick, given our current angular velocity, would pass. Turrets are
+    // assumed to be pointing along the solution.
+    pub miss_distance: f64,
+
+    gun: Gun,
+    // Our position, heading and velocity, and the entity's position, velocity
+    // and acceleration, at the time the bullet is fired.
+    origin: Vec2,
+    ship_heading: f64,
+    ship_vel: Vec2,
+    e_pos: Vec2,
+    e_vel: Vec2,
+    e_acc: Vec2,
+}
+
+impl FiringSolution {
+    // Returns None if a bullet can't reach the entity.
+    pub fn new(gun: &Gun, e_pos: Vec2, e_vel: Vec2, e_acc: Vec2) -> Option<FiringSolution> {
+        let acc = commanded_acceleration();
+        let origin = pos_after(position(), velocity(), acc, TICK_LENGTH);
+        let ship_vel = velocity() + acc * TICK_LENGTH;
+        let e_pos = pos_after(e_pos, e_vel, e_acc, TICK_LENGTH);
+        let e_vel = e_vel + e_acc * TICK_LENGTH;
+        let ship_heading = self::heading() + angular_velocity() * TICK_LENGTH;
+        let muzzle = |heading: f64| {
+            origin + gun.offset.rotate(if gun.turret { ship_heading } else { heading })
+        };
+
+        // The muzzle of a fixed gun moves with the heading we're solving for,
+        // so refine the solution a few times. The offset is small compared to
+        // the distances involved, so this converges quickly.
+        let mut heading = (e_pos - origin).angle();
+        let mut time_of_flight = 0.;
+        let mut aim_point = e_pos;
+        for _ in 0..3 {
+            let muzzle = muzzle(heading);
+            time_of_flight = intercept_time(e_pos - muzzle, e_vel - ship_vel, e_acc, gun.speed)?;
+            aim_point = pos_after(e_pos, e_vel, e_acc, time_of_flight);
+            // The bullet's own displacement, once our velocity is removed.
+            heading = (aim_point - muzzle - ship_vel * time_of_flight).angle();
+        }
+
+        let mut solution = FiringSolution {
+            heading,
+            time_of_flight,
+            aim_point,
+            miss_distance: 0.,
+            gun: *gun,
+            origin,
+            ship_heading,
+            ship_vel,
+            e_pos,
+            e_vel,
+            e_acc,
+        };
+        solution.miss_distance = solution.miss_distance_at(if gun.turret {
+            heading
+        } else {
+            ship_heading
+        });
+        Some(solution)
+    }
+
+    fn muzzle(&self, heading: f64) -> Vec2 {
+        let mount = if self.gun.turret { self.ship_heading } else { heading };
+        self.origin + self.gun.offset.rotate(mount)
+    }
+
+    // Whether the bullet gets to the entity before it disappears.
+    pub fn in_range(&self) -> bool {
+        self.time_of_flight <= self.gun.lifetime
+    }
 
-    let x0 = es.length() / b_spd;
-    newtons_method(&f, &fp, x0, Some(TICK_LENGTH / 10.), None)
-        .and_then(|t: f64| Some(pos_after(e_pos, e_vel, e_acc, t)))
+    // How close to the entity a bullet fired with the gun pointing at heading
+    // would pass before it disappears.
+    pub fn miss_distance_at(&self, heading: f64) -> f64 {
+        self.closest_approach(heading).offset.length()
+    }
+
+    // Where a bullet fired with the gun pointing at heading would be when it
+    // passes closest to the entity, before it disappears.
+    pub fn closest_approach(&self, heading: f64) -> Approach {
+        let muzzle = self.muzzle(heading);
+        let bullet_vel = self.ship_vel + to_unit(heading) * self.gun.speed;
+        // The entity's position relative to the bullet is a + b t + c t^2.
+        let a = self.e_pos - muzzle;
+        let b = self.e_vel - bullet_vel;
+        let c = self.e_acc * 0.5;
+        let approach = |time: f64| Approach {
+            time,
+            offset: a + time * (b + time * c),
+            relative_velocity: b + 2. * time * c,
+        };
+        // Closest approach is where the separation is perpendicular to its
+        // rate of change, or at either end of the bullet's life.
+        let lifetime = self.gun.lifetime;
+        cubic(2. * c.dot(c), 3. * b.dot(c), b.dot(b) + 2. * a.dot(c), a.dot(b))
+            .into_iter()
+            .filter(|&t| t > 0. && t < lifetime)
+            .chain([0., lifetime])
+            .map(approach)
+            .min_by(|x, y| x.offset.length().total_cmp(&y.offset.length()))
+            .unwrap()
+    }
 }
 
-pub fn turn_and_shoot_at(helm: &mut Helm, pos: Vec2, vel: Vec2, acc: Vec2, bspd: f64) {
-    if let Some(aimpoint) = lead(pos, vel, acc, bspd) {
-        if abs(helm.aim(aimpoint, bspd)) * 360. / (2. * PI) < 1.  {
-            fire(0);
+// A bullet's closest approach to an entity.
+#[derive(Clone, Copy, Debug)]
+pub struct Approach {
+    // Seconds after the bullet is fired.
+    pub time: f64,
+    // The entity's position relative to the bullet.
+    pub offset: Vec2,
+    // The entity's velocity relative to the bullet.
+    pub relative_velocity: Vec2,
+}
+
+// Turns toward the firing solution for an entity whose position we know
+// exactly, such as the tutorials' target(), and fires when a bullet would hit
+// it. The entity is assumed to be at least as big as a fighter.
+pub fn turn_and_shoot_at(helm: &mut Helm, pos: Vec2, vel: Vec2, acc: Vec2, gun: &Gun) {
+    FireControl::default().engage_at(
+        helm,
+        pos,
+        vel,
+        acc,
+        &Matrix::zeros(),
+        ship_dim(Class::Fighter) / 2.,
+        gun,
+    );
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_env::*;
+
+    #[test]
+    fn turn_small_error_hits_heading_next_tick() {
+        let mut env = Env::new(Class::Fighter);
+        env.set_heading(0.);
+        let err = Helm::new().turn(0.001);
+        assert_eq!(err, 0.);
+        // The commanded torque gets us exactly there in one tick.
+        let expected = 0.001 / TICK_LENGTH.powi(2);
+        assert!((env.torque() - expected).abs() < 1e-9, "{}", env.torque());
+    }
+
+    #[test]
+    fn turn_large_error_accelerates_toward_heading() {
+        let env = Env::new(Class::Fighter);
+        Helm::new().turn(1.);
+        assert_eq!(env.torque(), max_angular_acceleration());
+
+        Helm::new().turn(-1.);
+        assert_eq!(env.torque(), -max_angular_acceleration());
+    }
+
+    #[test]
+    fn turn_brakes_before_overshooting() {
+        let mut env = Env::new(Class::Fighter);
+        env.set_angular_velocity(3.);
+        Helm::new().turn(0.2);
+        assert!(env.torque() < 0., "{}", env.torque());
+    }
+
+    #[test]
+    fn turn_keeps_up_with_moving_heading() {
+        let mut sim = crate::sim::Sim::new(Class::Fighter);
+        let mut helm = Helm::new();
+        let target = |t: f64| 1. + 0.5 * t;
+        for _ in 0..(3. / TICK_LENGTH) as u32 {
+            sim.step(|| {
+                helm.turn(target(current_time()));
+            });
         }
+        // Facing where the heading is now, not where it was a tick ago.
+        let err = angle_diff(sim.ship.heading, target(sim.time()));
+        assert!(err.abs() < 1e-3, "{}", err);
+    }
+
+    #[test]
+    fn lead_stationary_target_is_target() {
+        let _env = Env::new(Class::Fighter);
+        let target = vec2(1000., 500.);
+        let aim = lead(target, vec2(0., 0.), vec2(0., 0.), 1000.).unwrap();
+        assert!((aim - target).length() < 1., "{:?}", aim);
+    }
+
+    #[test]
+    fn lead_moving_target_is_ahead_of_it() {
+        let _env = Env::new(Class::Fighter);
+        let target = vec2(1000., 0.);
+        let vel = vec2(0., 100.);
+        let aim = lead(target, vel, vec2(0., 0.), 1000.).unwrap();
+        // The bullet and the target arrive at the aim point at the same time.
+        let t = (aim - target).length() / vel.length();
+        assert!(((aim - position_next()).length() - t * 1000.).abs() < 1., "{:?}", aim);
+        assert!(aim.y > 0.);
+    }
+
+    #[test]
+    fn intercept_time_is_earliest_root() {
+        // A target heading straight at us from 3 km at 500 m/s. A bullet
+        // fired now meets it after 2 s; a bullet fired away from it could
+        // never catch it, so there's exactly one positive root.
+        let t = intercept_time(vec2(3000., 0.), vec2(-500., 0.), vec2(0., 0.), 1000.).unwrap();
+        assert!((t - 2.).abs() < 1e-9, "{}", t);
+
+        // A target faster than our bullets, crossing in front of us, can be
+        // hit on the way in or on the way out. We want the first chance.
+        let es = vec2(1000., -2000.);
+        let ev = vec2(0., 1500.);
+        let t = intercept_time(es, ev, vec2(0., 0.), 1000.).unwrap();
+        let roots = crate::roots::quadratic(
+            ev.dot(ev) - 1e6,
+            2. * es.dot(ev),
+            es.dot(es),
+        );
+        assert!((t - roots[0]).abs() < 1e-9, "{} {:?}", t, roots);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn intercept_time_accelerating_target() {
+        let es = vec2(2000., 500.);
+        let ev = vec2(-100., 200.);
+        let ea = vec2(30., -60.);
+        let t = intercept_time(es, ev, ea, 1000.).unwrap();
+        let meet = es + t * (ev + t * 0.5 * ea);
+        assert!((meet.length() - 1000. * t).abs() < 1e-6, "{}", t);
+    }
+
+    #[test]
+    fn intercept_time_barely_accelerating_target() {
+        // A tracker's estimate of a coasting target has a tiny acceleration.
+        // The answer should be next to the unaccelerated one, not the time
+        // at which the target finally outruns the bullet.
+        let (es, ev) = (vec2(-233., 1550.), vec2(40., -30.));
+        let coasting = intercept_time(es, ev, vec2(0., 0.), 1000.).unwrap();
+        for ea in [vec2(1e-2, -1e-2), vec2(1e-3, 1e-3)] {
+            let t = intercept_time(es, ev, ea, 1000.).unwrap();
+            assert!((t - coasting).abs() < 1e-3, "{:?} {} {}", ea, t, coasting);
+        }
+    }
+
+    #[test]
+    fn intercept_time_unreachable_target() {
+        // Running away faster than our bullets.
+        assert_eq!(intercept_time(vec2(1000., 0.), vec2(2000., 0.), vec2(0., 0.), 1000.), None);
+    }
+
+    // Follows a bullet fired along the solution's heading next tick and
+    // returns how close it gets to the entity.
+    fn fly_bullet(gun: &Gun, solution: &FiringSolution, e_pos: Vec2, e_vel: Vec2, e_acc: Vec2) -> f64 {
+        let acc = commanded_acceleration();
+        let origin = pos_after(position(), velocity(), acc, TICK_LENGTH);
+        let pos = origin + gun.offset.rotate(solution.heading);
+        let vel = velocity() + acc * TICK_LENGTH + to_unit(solution.heading) * gun.speed;
+        let t = solution.time_of_flight;
+        let target = pos_after(e_pos, e_vel, e_acc, TICK_LENGTH + t);
+        (pos + vel * t - target).length()
+    }
+
+    #[test]
+    fn firing_solution_hits_while_strafing() {
+        let mut env = Env::new(Class::Fighter);
+        env.set_velocity(vec2(0., 200.));
+        env.set_heading(0.3);
+        accelerate(vec2(0., 60.));
+        let (e_pos, e_vel, e_acc) = (vec2(1500., 800.), vec2(-120., 40.), vec2(10., -20.));
+        let solution = FiringSolution::new(&FIGHTER_GUN, e_pos, e_vel, e_acc).unwrap();
+        let miss = fly_bullet(&FIGHTER_GUN, &solution, e_pos, e_vel, e_acc);
+        assert!(miss < 0.01, "{}", miss);
+        assert!(solution.miss_distance_at(solution.heading) < 0.01);
+        assert!((solution.aim_point - pos_after(e_pos, e_vel, e_acc, TICK_LENGTH + solution.time_of_flight)).length() < 0.01);
+
+        // Aiming at lead() the way Helm::aim does ignores the strafe and the
+        // muzzle offset, and misses.
+        let aim = lead(e_pos, e_vel, e_acc, FIGHTER_GUN.speed).unwrap();
+        let shoot_vec = (aim - position_next()).normalize() * FIGHTER_GUN.speed - velocity();
+        let naive = FiringSolution {
+            heading: shoot_vec.angle(),
+            ..solution
+        };
+        let miss = fly_bullet(&FIGHTER_GUN, &naive, e_pos, e_vel, e_acc);
+        assert!(miss > 1., "{}", miss);
+    }
+
+    #[test]
+    fn miss_distance_grows_with_heading_error() {
+        let _env = Env::new(Class::Fighter);
+        let solution = FiringSolution::new(&FIGHTER_GUN, vec2(2000., 0.), vec2(0., 0.), vec2(0., 0.)).unwrap();
+        assert!(solution.miss_distance < 1e-6);
+        let miss = solution.miss_distance_at(0.01);
+        // Roughly the arc swept at the target's range.
+        assert!((miss - 20.).abs() < 1., "{}", miss);
+    }
+
+    #[test]
+    fn turn_and_shoot_at_fires_only_when_aligned() {
+        let mut env = Env::new(Class::Fighter);
+        let mut helm = Helm::new();
+        turn_and_shoot_at(&mut helm, vec2(1000., 0.), vec2(0., 0.), vec2(0., 0.), &FIGHTER_GUN);
+        assert!(env.fired(0));
+
+        env.advance_ticks(1);
+        turn_and_shoot_at(&mut helm, vec2(0., 1000.), vec2(0., 0.), vec2(0., 0.), &FIGHTER_GUN);
+        assert!(!env.fired(0));
+    }
+
+    #[test]
+    fn turn_and_shoot_at_holds_fire_out_of_range() {
+        let mut env = Env::new(Class::Fighter);
+        let mut helm = Helm::new();
+        // Dead ahead, but further than a bullet flies in its lifetime.
+        let far = vec2(FIGHTER_GUN.speed * FIGHTER_GUN.lifetime + 100., 0.);
+        turn_and_shoot_at(&mut helm, far, vec2(0., 0.), vec2(0., 0.), &FIGHTER_GUN);
+        assert!(!env.fired(0));
+
+        // Closing fast enough that the bullet gets there in time.
+        env.advance_ticks(1);
+        turn_and_shoot_at(&mut helm, far, vec2(-500., 0.), vec2(0., 0.), &FIGHTER_GUN);
+        assert!(env.fired(0));
+    }
+
+    #[test]
+    fn miss_distance_ignores_expired_bullets() {
+        let _env = Env::new(Class::Fighter);
+        let far = vec2(FIGHTER_GUN.speed * FIGHTER_GUN.lifetime + 100., 0.);
+        let solution = FiringSolution::new(&FIGHTER_GUN, far, vec2(0., 0.), vec2(0., 0.)).unwrap();
+        assert!(!solution.in_range());
+        // The bullet dies 80 m short: the muzzle offset makes up 20 m of the
+        // extra 100 m.
+        let miss = solution.miss_distance_at(solution.heading);
+        assert!(miss > 50., "{}", miss);
+    }
+}
@@ -0,0 +1,283 @@
+// Spotting incoming threats and dodging them.
+//
+// A threat is a missile or torpedo, which is coming for us whatever course it
+// is on right now, or any other contact closing fast on a course that passes
+// close to us. For each we estimate when it will be closest and how close
+// that is, and from those an overall threat level between 0 and 1 that other
+// behaviors can use, e.g. to break off an attack.
+//
+// While threatened we jink: short bursts of lateral acceleration across the
+// threat's line of sight, with random direction, strength and length. Enemy
+// lead computations assume we keep accelerating the way we are, so changing
+// it unpredictably spoils their aim. Jinks are added on top of whatever
+// acceleration has already been commanded this tick, so evasion should run
+// after the behavior that decides where we're going.
+use crate::{
+    contacts::{Contact, Contacts},
+    control::commanded_acceleration,
+    flight::max_acceleration_along,
+    radar::ship_dim,
+    roots::cubic,
+};
+use oort_api::prelude::{oorandom::Rand64, *};
+
+// We look this far ahead for threats.
+const HORIZON: f64 = 10.;
+
+// Contacts other than missiles closing faster than this are threats if
+// they'll pass within INTERCEPT_RADIUS of us.
+const FAST_CLOSING_SPEED: f64 = 300.;
+const INTERCEPT_RADIUS: f64 = 200.;
+
+// The bounds on how long each jink lasts, in seconds, and how hard it is as
+// a fraction of the acceleration we have available across the line of sight.
+const JINK_DURATION: (f64, f64) = (0.3, 1.2);
+const JINK_STRENGTH: (f64, f64) = (0.5, 1.);
+
+// A contact that is coming for us.
+#[derive(Clone, Copy, Debug)]
+pub struct Threat {
+    pub id: u32,
+    pub class: Class,
+    // Seconds until it is closest to us, assuming neither of us changes
+    // acceleration.
+    pub time_to_impact: f64,
+    // How close it passes.
+    pub miss_distance: f64,
+    // Where it is relative to us.
+    pub offset: Vec2,
+}
+
+impl Threat {
+    // Between 0 and 1: how soon the threat arrives, times how close to us
+    // it's going to pass. Missiles maneuver to hit us, so we take them to be
+    // on target whatever their current course.
+    pub fn level(&self) -> f64 {
+        let urgency = (1. - self.time_to_impact / HORIZON).clamp(0., 1.);
+        let closeness = if is_missile(self.class) {
+            1.
+        } else {
+            let danger = ship_dim(class()) / 2. + INTERCEPT_RADIUS;
+            (danger / self.miss_distance.max(danger)).clamp(0., 1.)
+        };
+        urgency * closeness
+    }
+}
+
+fn is_missile(class: Class) -> bool {
+    matches!(class, Class::Missile | Class::Torpedo)
+}
+
+// Whether the contact is a threat, and if so how.
+pub fn assess(contact: &Contact) -> Option<Threat> {
+    // The contact's position relative to us is a + b t + c t^2.
+    let a = contact.pos() - position();
+    let b = contact.vel() - velocity();
+    let c = contact.acc() * 0.5;
+    let los = if a.length() > 0. {
+        a.normalize()
+    } else {
+        vec2(1., 0.)
+    };
+    let closing_speed = -b.dot(los);
+    if closing_speed <= 0. {
+        return None;
+    }
+    let separation = |t: f64| (a + t * (b + t * c)).length();
+    let time_to_impact = cubic(
+        2. * c.dot(c),
+        3. * b.dot(c),
+        b.dot(b) + 2. * a.dot(c),
+        a.dot(b),
+    )
+    .into_iter()
+    .filter(|&t| t > 0. && t < HORIZON)
+    .chain([HORIZON])
+    .min_by(|&x, &y| separation(x).total_cmp(&separation(y)))
+    .unwrap();
+    let miss_distance = separation(time_to_impact);
+    let threat = Threat {
+        id: contact.id,
+        class: contact.class(),
+        time_to_impact,
+        miss_distance,
+        offset: a,
+    };
+    let on_course = closing_speed > FAST_CLOSING_SPEED
+        && miss_distance < INTERCEPT_RADIUS + ship_dim(class()) / 2.
+        && time_to_impact < HORIZON;
+    (is_missile(contact.class()) || on_course).then_some(threat)
+}
+
+struct Jink {
+    acc: Vec2,
+    until: f64,
+}
+
+pub struct Evasion {
+    threats: Vec<Threat>,
+    jink: Option<Jink>,
+    rng: Rand64,
+}
+
+impl Default for Evasion {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Evasion {
+    pub fn new() -> Evasion {
+        Evasion {
+            threats: Vec::new(),
+            jink: None,
+            rng: Rand64::new(seed()),
+        }
+    }
+
+    // The threats found on the last tick, most dangerous first.
+    pub fn threats(&self) -> &[Threat] {
+        &self.threats
+    }
+
+    // The level of the most dangerous threat, or 0 if there are none.
+    pub fn threat_level(&self) -> f64 {
+        self.threats.first().map_or(0., Threat::level)
+    }
+
+    // Assesses the contacts and jinks if any of them are a threat. Returns
+    // the threat level.
+    pub fn tick(&mut self, contacts: &Contacts) -> f64 {
+        self.threats = contacts.iter().filter_map(assess).collect();
+        self.threats.sort_by(|a, b| b.level().total_cmp(&a.level()));
+        let Some(threat) = self.threats.first().copied() else {
+            self.jink = None;
+            return 0.;
+        };
+
+        let now = current_time();
+        if self.jink.as_ref().is_none_or(|j| now >= j.until) {
+            let offset = threat.offset;
+            let across = if offset.length() > 0. {
+                vec2(-offset.y, offset.x).normalize()
+            } else {
+                vec2(1., 0.)
+            };
+            let dir = if self.rng.rand_float() < 0.5 {
+                across
+            } else {
+                -across
+            };
+            let strength = self.rand(JINK_STRENGTH);
+            self.jink = Some(Jink {
+                acc: dir * strength * max_acceleration_along(dir),
+                until: now + self.rand(JINK_DURATION),
+            });
+        }
+        let jink = self.jink.as_ref().unwrap().acc;
+        accelerate(commanded_acceleration() + jink);
+        draw_line(position(), position() + threat.offset, 0xff8000);
+        self.threat_level()
+    }
+
+    fn rand(&mut self, (low, high): (f64, f64)) -> f64 {
+        low + self.rng.rand_float() * (high - low)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{contacts::TrackPolicy, test_env::*};
+
+    fn contact(class: Class, pos: Vec2, vel: Vec2) -> Contact {
+        Contact::new(class, 0, pos, vel, 30., TrackPolicy::default())
+    }
+
+    #[test]
+    fn missiles_are_threats_on_any_course() {
+        let _env = Env::new(Class::Fighter);
+        // Heading straight for us from 2 km at 400 m/s.
+        let threat = assess(&contact(Class::Missile, vec2(2000., 0.), vec2(-400., 0.))).unwrap();
+        assert!((threat.time_to_impact - 5.).abs() < 1e-6, "{:?}", threat);
+        assert!(threat.miss_distance < 1e-6);
+        assert!((threat.level() - 0.5).abs() < 1e-6);
+        // Passing wide, but it will turn toward us.
+        let wide = assess(&contact(Class::Torpedo, vec2(2000., 0.), vec2(-400., 400.))).unwrap();
+        assert!(wide.miss_distance > 1000.);
+        // Going away.
+        assert!(assess(&contact(Class::Missile, vec2(2000., 0.), vec2(400., 0.))).is_none());
+    }
+
+    #[test]
+    fn ships_are_threats_only_on_intercept_course() {
+        let _env = Env::new(Class::Fighter);
+        let fast = assess(&contact(Class::Fighter, vec2(3000., 50.), vec2(-1000., 0.))).unwrap();
+        assert!((fast.miss_distance - 50.).abs() < 1e-6, "{:?}", fast);
+        assert!((fast.time_to_impact - 3.).abs() < 1e-6);
+        // Too slow to worry about.
+        assert!(assess(&contact(Class::Fighter, vec2(3000., 0.), vec2(-100., 0.))).is_none());
+        // Fast, but passing wide.
+        assert!(
+            assess(&contact(
+                Class::Fighter,
+                vec2(3000., 1000.),
+                vec2(-1000., 0.)
+            ))
+            .is_none()
+        );
+    }
+
+    #[test]
+    fn jinks_across_line_of_sight() {
+        let mut env = Env::new(Class::Fighter);
+        let mut contacts = Contacts::new();
+        let mut evasion = Evasion::new();
+        let mut jinks = Vec::new();
+        // It reaches us after 5 s.
+        for _ in 0..(4. / TICK_LENGTH) as u32 {
+            let pos = vec2(2000. - 400. * current_time(), 0.);
+            contacts.tick();
+            contacts.recv_contacts(vec![scan_result(Class::Missile, pos, vec2(-400., 0.))]);
+            let level = evasion.tick(&contacts);
+            assert!(level > 0.);
+            let acc = env.acceleration();
+            // Across the line of sight, within the lateral limit.
+            assert!(acc.x.abs() < 1e-6, "{:?}", acc);
+            assert!(
+                acc.y.abs() >= 15. - 1e-6 && acc.y.abs() <= 30. + 1e-6,
+                "{:?}",
+                acc
+            );
+            jinks.push(acc);
+            env.advance_ticks(1);
+        }
+        // The jinks change, but each lasts a while.
+        let changes = jinks.windows(2).filter(|w| w[0] != w[1]).count();
+        assert!((3..=13).contains(&changes), "{}", changes);
+    }
+
+    #[test]
+    fn contact_on_top_of_us_is_finite() {
+        let env = Env::new(Class::Fighter);
+        let c = contact(Class::Missile, vec2(0., 0.), vec2(-400., 0.));
+        let threat = assess(&c).unwrap();
+        assert!(threat.time_to_impact.is_finite() && threat.miss_distance.is_finite());
+        let mut contacts = Contacts::new();
+        contacts.recv_contacts(vec![scan_result(Class::Missile, vec2(0., 0.), vec2(-400., 0.))]);
+        let mut evasion = Evasion::new();
+        assert!(evasion.tick(&contacts).is_finite());
+        assert!(env.acceleration().x.is_finite() && env.acceleration().y.is_finite());
+    }
+
+    #[test]
+    fn no_threats_no_jinks() {
+        let env = Env::new(Class::Fighter);
+        let mut evasion = Evasion::new();
+        accelerate(vec2(10., 0.));
+        assert_eq!(evasion.tick(&Contacts::new()), 0.);
+        assert!(evasion.threats().is_empty());
+        // What was already commanded is left alone.
+        assert_eq!(env.acceleration(), vec2(10., 0.));
+    }
+}
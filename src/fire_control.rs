@@ -0,0 +1,228 @@
+// Decides when to pull the trigger.
+//
+// Rather than firing whenever we're pointing within some fixed angle of the
+// firing solution, we estimate the probability that the bullet hits and fire
+// when it's high enough. That takes into account everything that makes a
+// shot more or less likely to land: how far off our aim is, how big the
+// target is, and how well we know where it will be when the bullet gets
+// there, which grows worse with the time of flight.
+use crate::{
+    contacts::Contact,
+    control::{FiringSolution, Gun, Helm},
+    matrix::Matrix,
+    radar::ship_dim,
+};
+use oort_api::prelude::*;
+
+pub struct FireControl {
+    // We fire when the probability of a hit is at least this.
+    pub threshold: f64,
+}
+
+impl Default for FireControl {
+    fn default() -> Self {
+        Self::new(0.25)
+    }
+}
+
+impl FireControl {
+    pub fn new(threshold: f64) -> FireControl {
+        FireControl { threshold }
+    }
+
+    pub fn should_fire(
+        &self,
+        solution: &FiringSolution,
+        heading: f64,
+        covariance: &Matrix<2, 2>,
+        radius: f64,
+    ) -> bool {
+        solution.in_range()
+            && hit_probability(solution, heading, covariance, radius) >= self.threshold
+    }
+
+    // Turns toward the contact's firing solution and fires if the shot is
+    // likely enough to hit. Returns whether we fired.
+    pub fn engage(&self, helm: &mut Helm, contact: &Contact, gun: &Gun) -> bool {
+        let Some(solution) = FiringSolution::new(gun, contact.pos(), contact.vel(), contact.acc())
+        else {
+            return false;
+        };
+        let covariance = contact.pos_covariance_after(TICK_LENGTH + solution.time_of_flight);
+        self.engage_solution(helm, &solution, &covariance, ship_dim(contact.class()) / 2.)
+    }
+
+    // Like engage, for an entity that isn't a contact. covariance is the
+    // uncertainty in its position at the time the bullet reaches it and
+    // radius is its size.
+    #[allow(clippy::too_many_arguments)]
+    pub fn engage_at(
+        &self,
+        helm: &mut Helm,
+        pos: Vec2,
+        vel: Vec2,
+        acc: Vec2,
+        covariance: &Matrix<2, 2>,
+        radius: f64,
+        gun: &Gun,
+    ) -> bool {
+        let Some(solution) = FiringSolution::new(gun, pos, vel, acc) else {
+            return false;
+        };
+        self.engage_solution(helm, &solution, covariance, radius)
+    }
+
+    fn engage_solution(
+        &self,
+        helm: &mut Helm,
+        solution: &FiringSolution,
+        covariance: &Matrix<2, 2>,
+        radius: f64,
+    ) -> bool {
+        // Helm::turn returns how far off we'll be next tick, which is when
+        // the bullet leaves the gun.
+        let heading = solution.heading - helm.turn(solution.heading);
+        if self.should_fire(solution, heading, covariance, radius) {
+            fire(0);
+            return true;
+        }
+        false
+    }
+}
+
+// The probability that a bullet fired next tick with the gun pointing at
+// heading hits an entity of the given radius, whose position when the bullet
+// reaches it is distributed normally around the solution's prediction with
+// the given covariance.
+//
+// Only the error across the bullet's path matters: an entity that is a bit
+// ahead of or behind where we expect it is still on the bullet's path. So we
+// project the uncertainty onto the direction perpendicular to the path and
+// integrate the resulting 1D normal distribution over the width of the
+// entity.
+pub fn hit_probability(
+    solution: &FiringSolution,
+    heading: f64,
+    covariance: &Matrix<2, 2>,
+    radius: f64,
+) -> f64 {
+    let approach = solution.closest_approach(heading);
+    let v = approach.relative_velocity;
+    let across = if v.length() > 0. {
+        vec2(-v.y, v.x).normalize()
+    } else {
+        vec2(1., 0.)
+    };
+    let miss = approach.offset.dot(across);
+    let variance = across.x * across.x * covariance[(0, 0)]
+        + 2. * across.x * across.y * covariance[(0, 1)]
+        + across.y * across.y * covariance[(1, 1)];
+    if variance <= 0. {
+        return if miss.abs() < radius { 1. } else { 0. };
+    }
+    let stddev = variance.sqrt();
+    normal_cdf((radius - miss) / stddev) - normal_cdf((-radius - miss) / stddev)
+}
+
+fn normal_cdf(x: f64) -> f64 {
+    0.5 * (1. + erf(x / 2f64.sqrt()))
+}
+
+// Abramowitz and Stegun 7.1.26, accurate to about 1e-7.
+fn erf(x: f64) -> f64 {
+    let t = 1. / (1. + 0.3275911 * x.abs());
+    let poly = t
+        * (0.254829592
+            + t * (-0.284496736 + t * (1.421413741 + t * (-1.453152027 + t * 1.061405429))));
+    let y = 1. - poly * (-x * x).exp();
+    if x < 0. { -y } else { y }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{control::FIGHTER_GUN, test_env::*};
+
+    fn isotropic(stddev: f64) -> Matrix<2, 2> {
+        Matrix::diagonal([stddev * stddev; 2])
+    }
+
+    #[test]
+    fn erf_values() {
+        assert!(erf(0.).abs() < 1e-6);
+        assert!((erf(1.) - 0.8427008).abs() < 1e-6);
+        assert!((erf(-1.) + 0.8427008).abs() < 1e-6);
+        assert!((normal_cdf(1.96) - 0.975).abs() < 1e-3);
+    }
+
+    #[test]
+    fn exact_target_is_hit_only_when_aimed_at() {
+        let _env = Env::new(Class::Fighter);
+        let solution =
+            FiringSolution::new(&FIGHTER_GUN, vec2(1000., 0.), vec2(0., 0.), vec2(0., 0.)).unwrap();
+        let exact = Matrix::zeros();
+        assert_eq!(
+            hit_probability(&solution, solution.heading, &exact, 10.),
+            1.
+        );
+        // 0.02 rad at ~1 km is ~20 m off.
+        assert_eq!(
+            hit_probability(&solution, solution.heading + 0.02, &exact, 10.),
+            0.
+        );
+    }
+
+    #[test]
+    fn uncertainty_lowers_hit_probability() {
+        let _env = Env::new(Class::Fighter);
+        let solution =
+            FiringSolution::new(&FIGHTER_GUN, vec2(1000., 0.), vec2(0., 0.), vec2(0., 0.)).unwrap();
+        let p = |stddev| hit_probability(&solution, solution.heading, &isotropic(stddev), 10.);
+        assert!(p(1.) > 0.99);
+        // One standard deviation either side.
+        assert!((p(10.) - 0.6827).abs() < 1e-3, "{}", p(10.));
+        assert!(p(100.) < 0.1);
+    }
+
+    #[test]
+    fn only_cross_track_uncertainty_matters() {
+        let _env = Env::new(Class::Fighter);
+        // We fire along x, so uncertainty in x is along the bullet's path.
+        let solution =
+            FiringSolution::new(&FIGHTER_GUN, vec2(1000., 0.), vec2(0., 0.), vec2(0., 0.)).unwrap();
+        let along = Matrix::diagonal([100f64.powi(2), 0.]);
+        let across = Matrix::diagonal([0., 100f64.powi(2)]);
+        assert_eq!(
+            hit_probability(&solution, solution.heading, &along, 10.),
+            1.
+        );
+        assert!(hit_probability(&solution, solution.heading, &across, 10.) < 0.1);
+    }
+
+    #[test]
+    fn fires_above_threshold() {
+        let env = Env::new(Class::Fighter);
+        let mut helm = Helm::new();
+        let pos = vec2(1000., 0.);
+        let zero = vec2(0., 0.);
+        assert!(FireControl::new(0.5).engage_at(
+            &mut helm,
+            pos,
+            zero,
+            zero,
+            &isotropic(10.),
+            10.,
+            &FIGHTER_GUN
+        ));
+        assert!(env.fired(0));
+        assert!(!FireControl::new(0.9).engage_at(
+            &mut helm,
+            pos,
+            zero,
+            zero,
+            &isotropic(10.),
+            10.,
+            &FIGHTER_GUN
+        ));
+    }
+}
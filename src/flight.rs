@@ -0,0 +1,161 @@
+// Translational flight: where the ship goes, as opposed to which way it
+// points.
+//
+// Helm turns the ship with torque(). Everything here only calls accelerate(),
+// so the two compose freely: we can fly to a point while the helm keeps the
+// guns on a target off to one side. The catch is that how hard we can
+// accelerate depends on which way we're pointing, and the helm may turn us at
+// any moment. So we plan to brake with the acceleration we can count on
+// whatever our heading, and accelerate with whatever we actually have.
+use crate::control::commanded_acceleration;
+use oort_api::prelude::*;
+
+// The largest acceleration we can apply along dir (in world coordinates)
+// with our current heading. Ships accelerate hardest forward, and less hard
+// backward or sideways.
+pub fn max_acceleration_along(dir: Vec2) -> f64 {
+    let d = dir.normalize().rotate(-heading());
+    let mut max = f64::INFINITY;
+    if d.x > 0. {
+        max = max.min(max_forward_acceleration() / d.x);
+    }
+    if d.x < 0. {
+        max = max.min(max_backward_acceleration() / -d.x);
+    }
+    if d.y != 0. {
+        max = max.min(max_lateral_acceleration() / d.y.abs());
+    }
+    max
+}
+
+// The acceleration we can apply in any direction, however we're pointing.
+pub fn braking_acceleration() -> f64 {
+    max_forward_acceleration()
+        .min(max_backward_acceleration())
+        .min(max_lateral_acceleration())
+}
+
+// The highest speed at which we can still stop within distance, braking at
+// acc. The game applies our acceleration over a whole tick, so we start
+// braking half a tick's worth of speed early; for a continuous system this
+// would just be sqrt(2 acc distance).
+pub fn stopping_speed(distance: f64, acc: f64) -> f64 {
+    let half_tick = acc * TICK_LENGTH / 2.;
+    (2. * acc * distance + half_tick * half_tick).sqrt() - half_tick
+}
+
+// Accelerates to reach the given velocity as quickly as possible. Returns the
+// acceleration commanded, after it's been limited to what the ship can do.
+pub fn match_velocity(vel: Vec2) -> Vec2 {
+    accelerate((vel - velocity()) / TICK_LENGTH);
+    commanded_acceleration()
+}
+
+// Flies to a point that is moving with velocity vel and stops there, i.e.
+// ends up moving with it. Our speed relative to the point is capped at
+// max_speed. Returns the acceleration commanded.
+//
+// This is bang-bang control: accelerate flat out toward the point until we're
+// going as fast as we can while still being able to stop there, then brake
+// flat out. Any velocity across the line to the point is cancelled along the
+// way.
+pub fn fly_to(pos: Vec2, vel: Vec2, max_speed: f64) -> Vec2 {
+    let offset = pos - position();
+    let distance = offset.length();
+    if distance == 0. {
+        return match_velocity(vel);
+    }
+    let speed = stopping_speed(distance, braking_acceleration()).min(max_speed);
+    match_velocity(vel + offset / distance * speed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{control::Helm, sim::Sim, test_env::*};
+
+    #[test]
+    fn acceleration_depends_on_heading() {
+        let mut env = Env::new(Class::Fighter);
+        env.set_heading(PI / 2.);
+        assert!((max_acceleration_along(vec2(0., 1.)) - 60.).abs() < 1e-9);
+        assert!((max_acceleration_along(vec2(0., -1.)) - 30.).abs() < 1e-9);
+        assert!((max_acceleration_along(vec2(1., 0.)) - 30.).abs() < 1e-9);
+        // Half forward and half to the side: the lateral limit binds.
+        let diagonal = max_acceleration_along(vec2(1., 1.));
+        assert!((diagonal - 30. * 2f64.sqrt()).abs() < 1e-9, "{}", diagonal);
+        assert_eq!(braking_acceleration(), 30.);
+    }
+
+    #[test]
+    fn match_velocity_accelerates_flat_out() {
+        let env = Env::new(Class::Fighter);
+        let acc = match_velocity(vec2(0., 1000.));
+        assert!((acc - vec2(0., 30.)).length() < 1e-9, "{:?}", acc);
+        assert!((env.acceleration() - acc).length() < 1e-9);
+
+        // Close enough to get there in one tick.
+        let acc = match_velocity(vec2(0.5, 0.));
+        assert!((acc - vec2(0.5 / TICK_LENGTH, 0.)).length() < 1e-9, "{:?}", acc);
+    }
+
+    #[test]
+    fn stopping_speed_is_consistent_with_braking() {
+        // Braking at 30 m/s^2 from 300 m/s takes 10 s and 1500 m, give or take
+        // the half tick.
+        let speed = stopping_speed(1500., 30.);
+        assert!((speed - 300.).abs() < 0.5, "{}", speed);
+        assert_eq!(stopping_speed(0., 30.), 0.);
+    }
+
+    #[test]
+    fn flies_to_point_while_pointing_elsewhere() {
+        let mut sim = Sim::new(Class::Fighter);
+        let mut helm = Helm::new();
+        let goal = vec2(2000., 1000.);
+        let ticks = sim.run_until(
+            (20. / TICK_LENGTH) as u32,
+            || {
+                fly_to(goal, vec2(0., 0.), f64::INFINITY);
+                helm.turn(-PI / 2.);
+            },
+            |sim| (sim.ship.pos - goal).length() < 1. && sim.ship.vel.length() < 1.,
+        );
+        // Flat out at 30 m/s^2 the whole way it would take about 17 s.
+        assert!(ticks.is_some());
+        assert!(angle_diff(sim.ship.heading, -PI / 2.).abs() < 0.01);
+    }
+
+    #[test]
+    fn speed_cap_is_respected() {
+        let mut sim = Sim::new(Class::Fighter);
+        let goal = vec2(5000., 0.);
+        let mut top_speed = 0f64;
+        for _ in 0..(60. / TICK_LENGTH) as u32 {
+            sim.step(|| {
+                fly_to(goal, vec2(0., 0.), 100.);
+            });
+            top_speed = top_speed.max(sim.ship.vel.length());
+        }
+        assert!((sim.ship.pos - goal).length() < 1.);
+        assert!(top_speed <= 100. + 1e-6, "{}", top_speed);
+    }
+
+    #[test]
+    fn keeps_station_on_moving_point() {
+        let mut sim = Sim::new(Class::Fighter);
+        let (start, vel) = (vec2(500., -500.), vec2(50., 20.));
+        let ticks = sim.run_until(
+            (30. / TICK_LENGTH) as u32,
+            || {
+                let goal = start + vel * current_time();
+                fly_to(goal, vel, f64::INFINITY);
+            },
+            |sim| {
+                let goal = start + vel * sim.time();
+                (sim.ship.pos - goal).length() < 1. && (sim.ship.vel - vel).length() < 1.
+            },
+        );
+        assert!(ticks.is_some());
+    }
+}
@@ -1,21 +1,140 @@
-// Tutorial: Lead
-// Destroy the enemy ship. Its position is given by the "target" function and velocity by the
-// "target_velocity" function. Your ship is not able to accelerate in this scenario.
+// The AI the game runs. Each scenario has its own module with its own Ship;
+// the Ship here picks one based on the scenario being played and, outside of
+// the tutorials, on the class of the ship it controls. That way a single
+// build can play every scenario.
 //
-// This is where the game becomes challenging! You'll need to lead the target
-// by firing towards where the target will be by the time the bullet gets there.
-//
-// Hint: target() + target_velocity() * t gives the position of the target after t seconds.
-//
-// You can scale a vector by a number: vec2(a, b) * c == vec2(a * c, b * c)
-//
-// p.s. You can change your username by clicking on it at the top of the page.
+// The scenario_* cargo features pin a build to one scenario regardless of
+// what the game reports, e.g. for testing a tutorial AI in another scenario.
+use oort_api::prelude::*;
+
 pub mod control;
 pub mod tutorial_rotation;
 pub mod tutorial_lead;
 pub mod tutorial_deflection;
 
+pub mod assignment;
+pub mod collision;
 pub mod contacts;
+pub mod evasion;
+pub mod fire_control;
+pub mod flight;
+pub mod matrix;
+pub mod missile;
+pub mod navigation;
+pub mod orbit;
 pub mod radar;
+pub mod roots;
 pub mod tutorial_radar;
-pub mod tutorial_search;
\ No newline at end of file
+pub mod tutorial_search;
+pub mod weapons;
+#[cfg(any(test, feature = "sim"))]
+pub mod sim;
+#[cfg(any(test, feature = "sim"))]
+mod test_env;
+
+pub enum Ship {
+    Rotation(tutorial_rotation::Ship),
+    Lead(tutorial_lead::Ship),
+    Deflection(tutorial_deflection::Ship),
+    Radar(tutorial_radar::Ship),
+    Search(tutorial_search::Ship),
+    Missile(missile::Ship),
+    // A ship we have no AI for. It does nothing.
+    Idle,
+}
+
+fn scenario() -> &'static str {
+    if cfg!(feature = "scenario_tutorial_rotation") {
+        "tutorial_rotation"
+    } else if cfg!(feature = "scenario_tutorial_lead") {
+        "tutorial_lead"
+    } else if cfg!(feature = "scenario_tutorial_deflection") {
+        "tutorial_deflection"
+    } else if cfg!(feature = "scenario_tutorial_radar") {
+        "tutorial_radar"
+    } else if cfg!(feature = "scenario_tutorial_search") {
+        "tutorial_search"
+    } else {
+        scenario_name()
+    }
+}
+
+impl Default for Ship {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Ship {
+    pub fn new() -> Ship {
+        // Missiles launched in any scenario run the missile AI.
+        if matches!(class(), Class::Missile | Class::Torpedo) {
+            return Ship::Missile(missile::Ship::new());
+        }
+        match scenario() {
+            "tutorial_rotation" => Ship::Rotation(tutorial_rotation::Ship::new()),
+            "tutorial_lead" => Ship::Lead(tutorial_lead::Ship::new()),
+            "tutorial_deflection" => Ship::Deflection(tutorial_deflection::Ship::new()),
+            "tutorial_radar" => Ship::Radar(tutorial_radar::Ship::new()),
+            "tutorial_search" => Ship::Search(tutorial_search::Ship::new()),
+            // Duels, furballs and fleet battles. The search AI is the most
+            // general one we have: it finds targets with the radar and tracks
+            // them, so use it for anything with a gun.
+            _ => match class() {
+                Class::Fighter | Class::Frigate | Class::Cruiser => {
+                    Ship::Search(tutorial_search::Ship::new())
+                }
+                _ => Ship::Idle,
+            },
+        }
+    }
+
+    pub fn tick(&mut self) {
+        match self {
+            Ship::Rotation(ship) => ship.tick(),
+            Ship::Lead(ship) => ship.tick(),
+            Ship::Deflection(ship) => ship.tick(),
+            Ship::Radar(ship) => ship.tick(),
+            Ship::Search(ship) => ship.tick(),
+            Ship::Missile(ship) => ship.tick(),
+            Ship::Idle => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{sim::Sim, test_env::Env};
+
+    #[test]
+    fn tutorial_scenarios_use_their_ship() {
+        let mut env = Env::new(Class::Fighter);
+        env.set_scenario_name("tutorial_lead");
+        assert!(matches!(Ship::new(), Ship::Lead(_)));
+        env.set_scenario_name("tutorial_search");
+        assert!(matches!(Ship::new(), Ship::Search(_)));
+    }
+
+    #[test]
+    fn other_scenarios_dispatch_on_class() {
+        {
+            let mut env = Env::new(Class::Frigate);
+            env.set_scenario_name("frigate_duel");
+            assert!(matches!(Ship::new(), Ship::Search(_)));
+        }
+        let mut env = Env::new(Class::Missile);
+        env.set_scenario_name("tutorial_lead");
+        assert!(matches!(Ship::new(), Ship::Missile(_)));
+    }
+
+    #[test]
+    fn plays_tutorial_lead() {
+        let mut sim = Sim::new(Class::Fighter);
+        sim.set_scenario_name("tutorial_lead");
+        sim.add_target(Class::Target, vec2(2000., 1000.), vec2(-50., 150.));
+        let mut ship = Ship::new();
+        let ticks = sim.run_until(30 * 60, || ship.tick(), Sim::all_targets_destroyed);
+        assert!(ticks.is_some());
+    }
+}
@@ -0,0 +1,231 @@
+use std::ops::{Add, AddAssign, Index, IndexMut, Mul, Neg, Sub};
+
+// A small fixed-size dense matrix. maths_rs only provides matrices up to 4x4,
+// which is not enough for the tracking filters in contacts.rs, so we roll our
+// own. Everything is stack allocated and sized at compile time, which keeps
+// the code usable in the single-file environment of the game.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Matrix<const R: usize, const C: usize> {
+    m: [[f64; C]; R],
+}
+
+// A column vector.
+pub type Vector<const N: usize> = Matrix<N, 1>;
+
+impl<const R: usize, const C: usize> Matrix<R, C> {
+    pub fn zeros() -> Self {
+        Matrix { m: [[0.; C]; R] }
+    }
+
+    pub fn from_rows(m: [[f64; C]; R]) -> Self {
+        Matrix { m }
+    }
+
+    pub fn transpose(&self) -> Matrix<C, R> {
+        let mut t = Matrix::<C, R>::zeros();
+        for r in 0..R {
+            for c in 0..C {
+                t.m[c][r] = self.m[r][c];
+            }
+        }
+        t
+    }
+}
+
+impl<const N: usize> Matrix<N, N> {
+    pub fn identity() -> Self {
+        let mut m = Self::zeros();
+        for i in 0..N {
+            m.m[i][i] = 1.;
+        }
+        m
+    }
+
+    pub fn diagonal(d: [f64; N]) -> Self {
+        let mut m = Self::zeros();
+        for (i, x) in d.into_iter().enumerate() {
+            m.m[i][i] = x;
+        }
+        m
+    }
+
+    // Returns (M + M^T) / 2. Used to scrub the asymmetry that rounding errors
+    // introduce into matrices that should be symmetric.
+    pub fn symmetrized(&self) -> Self {
+        (*self + self.transpose()) * 0.5
+    }
+
+    pub fn trace(&self) -> f64 {
+        (0..N).map(|i| self.m[i][i]).sum()
+    }
+
+    // Computes the determinant with Gaussian elimination and partial
+    // pivoting.
+    pub fn determinant(&self) -> f64 {
+        let mut a = self.m;
+        let mut det = 1.;
+        for col in 0..N {
+            let pivot = (col..N)
+                .max_by(|&i, &j| a[i][col].abs().total_cmp(&a[j][col].abs()))
+                .unwrap();
+            if a[pivot][col] == 0. {
+                return 0.;
+            }
+            if pivot != col {
+                a.swap(col, pivot);
+                det = -det;
+            }
+            det *= a[col][col];
+            let (upper, lower) = a.split_at_mut(col + 1);
+            let pivot_row = &upper[col];
+            for row in lower {
+                let f = row[col] / pivot_row[col];
+                for (x, p) in row[col..].iter_mut().zip(&pivot_row[col..]) {
+                    *x -= f * p;
+                }
+            }
+        }
+        det
+    }
+
+    // Inverts the matrix with Gauss-Jordan elimination and partial pivoting.
+    // Returns None if the matrix is singular (or close enough to it that the
+    // result would be garbage).
+    pub fn inverse(&self) -> Option<Self> {
+        let mut a = self.m;
+        let mut inv = Self::identity().m;
+        for col in 0..N {
+            let pivot = (col..N).max_by(|&i, &j| a[i][col].abs().total_cmp(&a[j][col].abs()))?;
+            if a[pivot][col].abs() < f64::EPSILON {
+                return None;
+            }
+            a.swap(col, pivot);
+            inv.swap(col, pivot);
+
+            let p = a[col][col];
+            for c in 0..N {
+                a[col][c] /= p;
+                inv[col][c] /= p;
+            }
+            for r in 0..N {
+                if r == col {
+                    continue;
+                }
+                let f = a[r][col];
+                if f == 0. {
+                    continue;
+                }
+                for c in 0..N {
+                    a[r][c] -= f * a[col][c];
+                    inv[r][c] -= f * inv[col][c];
+                }
+            }
+        }
+        Some(Matrix { m: inv })
+    }
+}
+
+impl<const N: usize> Vector<N> {
+    pub fn new(v: [f64; N]) -> Self {
+        let mut m = Self::zeros();
+        for (i, x) in v.into_iter().enumerate() {
+            m.m[i][0] = x;
+        }
+        m
+    }
+
+    pub fn dot(&self, other: &Self) -> f64 {
+        (0..N).map(|i| self.m[i][0] * other.m[i][0]).sum()
+    }
+}
+
+impl<const R: usize, const C: usize> Index<(usize, usize)> for Matrix<R, C> {
+    type Output = f64;
+
+    fn index(&self, (r, c): (usize, usize)) -> &f64 {
+        &self.m[r][c]
+    }
+}
+
+impl<const R: usize, const C: usize> IndexMut<(usize, usize)> for Matrix<R, C> {
+    fn index_mut(&mut self, (r, c): (usize, usize)) -> &mut f64 {
+        &mut self.m[r][c]
+    }
+}
+
+// Vectors can be indexed by a single usize.
+impl<const N: usize> Index<usize> for Vector<N> {
+    type Output = f64;
+
+    fn index(&self, i: usize) -> &f64 {
+        &self.m[i][0]
+    }
+}
+
+impl<const N: usize> IndexMut<usize> for Vector<N> {
+    fn index_mut(&mut self, i: usize) -> &mut f64 {
+        &mut self.m[i][0]
+    }
+}
+
+impl<const R: usize, const C: usize> Add for Matrix<R, C> {
+    type Output = Self;
+
+    fn add(mut self, rhs: Self) -> Self {
+        self += rhs;
+        self
+    }
+}
+
+impl<const R: usize, const C: usize> AddAssign for Matrix<R, C> {
+    fn add_assign(&mut self, rhs: Self) {
+        for r in 0..R {
+            for c in 0..C {
+                self.m[r][c] += rhs.m[r][c];
+            }
+        }
+    }
+}
+
+impl<const R: usize, const C: usize> Sub for Matrix<R, C> {
+    type Output = Self;
+
+    fn sub(self, rhs: Self) -> Self {
+        self + -rhs
+    }
+}
+
+impl<const R: usize, const C: usize> Neg for Matrix<R, C> {
+    type Output = Self;
+
+    fn neg(self) -> Self {
+        self * -1.
+    }
+}
+
+impl<const R: usize, const C: usize> Mul<f64> for Matrix<R, C> {
+    type Output = Self;
+
+    fn mul(mut self, rhs: f64) -> Self {
+        for r in 0..R {
+            for c in 0..C {
+                self.m[r][c] *= rhs;
+            }
+        }
+        self
+    }
+}
+
+impl<const R: usize, const C: usize, const K: usize> Mul<Matrix<C, K>> for Matrix<R, C> {
+    type Output = Matrix<R, K>;
+
+    fn mul(self, rhs: Matrix<C, K>) -> Matrix<R, K> {
+        let mut out = Matrix::<R, K>::zeros();
+        for r in 0..R {
+            for k in 0..K {
+                out.m[r][k] = (0..C).map(|c| self.m[r][c] * rhs.m[c][k]).sum();
+            }
+        }
+        out
+    }
+}
@@ -0,0 +1,272 @@
+// Missiles and torpedoes: deciding when to launch them, and guiding them once
+// they're in flight.
+//
+// A launched missile is a ship of its own running our AI, with class Missile
+// or Torpedo. It knows nothing about what it was launched at, so it finds a
+// target with its own radar: it's launched toward the contact, so the nearest
+// contact it sees is usually the right one.
+use crate::{contacts::*, control::Helm, radar::*};
+use oort_api::prelude::*;
+use std::collections::HashMap;
+
+// A missile or torpedo launcher and the index the game knows it by in aim()
+// and fire().
+#[derive(Clone, Copy, Debug)]
+pub struct Launcher {
+    pub index: usize,
+    // Missile or Torpedo.
+    pub class: Class,
+    // How far away we launch at contacts. Beyond this the missile is likely
+    // to be outmaneuvered before it gets there.
+    pub range: f64,
+}
+
+const MISSILE_RANGE: f64 = 4000.;
+const TORPEDO_RANGE: f64 = 8000.;
+
+// The launchers on a ship of the given class.
+pub fn launchers(class: Class) -> Vec<Launcher> {
+    let missile = |index| Launcher {
+        index,
+        class: Class::Missile,
+        range: MISSILE_RANGE,
+    };
+    match class {
+        Class::Fighter => vec![missile(1)],
+        Class::Frigate => vec![missile(3)],
+        Class::Cruiser => vec![
+            missile(1),
+            missile(2),
+            Launcher {
+                index: 3,
+                class: Class::Torpedo,
+                range: TORPEDO_RANGE,
+            },
+        ],
+        _ => Vec::new(),
+    }
+}
+
+// How long to wait between launches at the same contact, to give the missile
+// already on its way a chance.
+const RELAUNCH_DELAY: f64 = 2.;
+
+pub struct Launchers {
+    launchers: Vec<Launcher>,
+    // When we last launched at each contact, by id.
+    last_launch: HashMap<u32, f64>,
+}
+
+impl Launchers {
+    pub fn new(class: Class) -> Launchers {
+        Launchers {
+            launchers: launchers(class),
+            last_launch: HashMap::new(),
+        }
+    }
+
+    // Launches from every loaded launcher that has a confirmed contact in
+    // range. Torpedoes are slow to turn, so they're saved for frigates and
+    // cruisers. Returns the number of launches.
+    pub fn tick(&mut self, contacts: &Contacts) -> usize {
+        let now = current_time();
+        self.last_launch.retain(|_, t| now - *t < RELAUNCH_DELAY);
+        let mut launched = 0;
+        for launcher in &self.launchers {
+            if reload_ticks(launcher.index) > 0 {
+                continue;
+            }
+            let target = contacts
+                .confirmed()
+                .filter(|c| !self.last_launch.contains_key(&c.id))
+                .filter(|c| {
+                    launcher.class != Class::Torpedo
+                        || matches!(c.class(), Class::Frigate | Class::Cruiser)
+                })
+                .map(|c| (c, (c.pos() - position()).length()))
+                .filter(|&(_, d)| d < launcher.range)
+                .min_by(|a, b| a.1.total_cmp(&b.1));
+            let Some((contact, _)) = target else {
+                continue;
+            };
+            aim(launcher.index, (contact.pos() - position()).angle());
+            fire(launcher.index);
+            self.last_launch.insert(contact.id, now);
+            launched += 1;
+        }
+        launched
+    }
+}
+
+// The navigation constant for proportional navigation. 3 to 5 is typical.
+const NAVIGATION_CONSTANT: f64 = 4.;
+
+// Returns the acceleration that augmented proportional navigation commands
+// for a target at relative position r, with relative velocity v, which is
+// itself accelerating at a.
+//
+// Plain PN turns us at N times the rate the line of sight rotates, which puts
+// us on a collision course with a target moving in a straight line. The
+// augmented form adds N/2 times the target's acceleration across the line of
+// sight, so that a maneuvering target doesn't leave us chasing it.
+pub fn pn_acceleration(r: Vec2, v: Vec2, a: Vec2, n: f64) -> Vec2 {
+    let range_sq = r.dot(r);
+    if range_sq == 0. {
+        return vec2(0., 0.);
+    }
+    let los = r / range_sq.sqrt();
+    let across = vec2(-los.y, los.x);
+    let los_rate = (r.x * v.y - r.y * v.x) / range_sq;
+    let closing_speed = -v.dot(los);
+    across * (n * closing_speed * los_rate + n / 2. * a.dot(across))
+}
+
+// How close the target has to pass, on top of its own size, for the warhead
+// to do its job.
+const FUSE_MARGIN: f64 = 15.;
+
+// Whether a target at relative position r, with relative velocity v and the
+// given radius, comes within fusing distance before the next tick.
+pub fn should_detonate(r: Vec2, v: Vec2, radius: f64) -> bool {
+    let speed_sq = v.dot(v);
+    let t = if speed_sq > 0. {
+        (-r.dot(v) / speed_sq).clamp(0., TICK_LENGTH)
+    } else {
+        0.
+    };
+    (r + v * t).length() < radius + FUSE_MARGIN
+}
+
+// The AI for a missile or torpedo in flight.
+pub struct Ship {
+    contacts: Contacts,
+    radar: Radar,
+    helm: Helm,
+    target: Option<u32>,
+}
+
+impl Default for Ship {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Ship {
+    pub fn new() -> Ship {
+        Ship {
+            contacts: Contacts::new(),
+            radar: Radar::new(),
+            helm: Helm::new(),
+            target: None,
+        }
+    }
+
+    pub fn tick(&mut self) {
+        self.contacts.tick();
+        self.radar.tick(&mut self.contacts);
+
+        // Stick with our target for as long as we track it, otherwise go for
+        // whatever is nearest.
+        self.target = self
+            .target
+            .filter(|&id| self.contacts.at(id).is_some())
+            .or_else(|| {
+                self.contacts
+                    .iter()
+                    .min_by(|a, b| {
+                        let da = (a.pos() - position()).length();
+                        let db = (b.pos() - position()).length();
+                        da.total_cmp(&db)
+                    })
+                    .map(|c| c.id)
+            });
+        let Some(contact) = self.target.and_then(|id| self.contacts.at(id)) else {
+            // Keep going the way we were launched while the radar searches.
+            accelerate(vec2(max_forward_acceleration(), 0.).rotate(heading()));
+            return;
+        };
+
+        let r = contact.pos() - position();
+        let v = contact.vel() - velocity();
+        if should_detonate(r, v, ship_dim(contact.class()) / 2.) {
+            explode();
+            return;
+        }
+
+        // Steer with PN, and spend whatever thrust is left closing in.
+        let max = max_forward_acceleration();
+        let mut steer = pn_acceleration(r, v, contact.acc(), NAVIGATION_CONSTANT);
+        if steer.length() > max {
+            steer = steer.normalize() * max;
+        }
+        let close = r.normalize() * (max * max - steer.dot(steer)).sqrt();
+        let acc = steer + close;
+        self.helm.turn(acc.angle());
+        accelerate(acc);
+        draw_line(position(), contact.pos(), 0xff0000);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_env::*;
+
+    #[test]
+    fn pn_ignores_target_on_constant_bearing() {
+        // Head on: the line of sight doesn't rotate, so there's nothing to
+        // correct.
+        let a = pn_acceleration(vec2(1000., 0.), vec2(-300., 0.), vec2(0., 0.), 4.);
+        assert!(a.length() < 1e-9);
+    }
+
+    #[test]
+    fn pn_leads_crossing_target() {
+        // The target is moving up across our line of sight, so we turn up.
+        let a = pn_acceleration(vec2(1000., 0.), vec2(-300., 100.), vec2(0., 0.), 4.);
+        assert!(a.y > 0. && a.x.abs() < 1e-9, "{:?}", a);
+        // Augmented PN adds the target's acceleration across the line of
+        // sight, but not along it.
+        let aug = pn_acceleration(vec2(1000., 0.), vec2(-300., 100.), vec2(50., 20.), 4.);
+        assert!((aug.y - a.y - 40.).abs() < 1e-9, "{:?}", aug);
+    }
+
+    #[test]
+    fn detonates_only_when_close() {
+        assert!(should_detonate(vec2(20., 0.), vec2(-100., 0.), 10.));
+        // Will pass within range during the next tick.
+        assert!(should_detonate(vec2(50., 5.), vec2(-3000., 0.), 10.));
+        assert!(!should_detonate(vec2(500., 0.), vec2(-300., 0.), 10.));
+        // Passes wide.
+        assert!(!should_detonate(vec2(50., 100.), vec2(-3000., 0.), 10.));
+    }
+
+    #[test]
+    fn launches_at_contacts_in_range() {
+        let mut env = Env::new(Class::Cruiser);
+        let mut contacts = Contacts::new();
+        let scans = || {
+            vec![
+                scan_result(Class::Fighter, vec2(2000., 0.), vec2(0., 0.)),
+                scan_result(Class::Frigate, vec2(-6000., 0.), vec2(0., 0.)),
+            ]
+        };
+        contacts.recv_contacts(scans());
+        env.advance_ticks(1);
+        contacts.tick();
+        contacts.recv_contacts(scans());
+
+        let mut launchers = Launchers::new(Class::Cruiser);
+        assert_eq!(launchers.tick(&contacts), 2);
+        // The first missile launcher takes the fighter. The second has
+        // nothing left in range and the torpedo goes for the frigate.
+        assert!(env.fired(1));
+        assert!(!env.fired(2));
+        assert!(env.fired(3));
+        assert!(angle_diff(env.aimed(3), PI).abs() < 0.01);
+
+        // Don't launch again straight away.
+        env.advance_ticks(1);
+        assert_eq!(launchers.tick(&contacts), 0);
+    }
+}
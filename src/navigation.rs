@@ -0,0 +1,343 @@
+// Following a route of waypoints.
+//
+// The Navigator only calls accelerate(), through flight::fly_to and friends,
+// so the helm stays free to point the guns while we travel.
+use crate::flight::*;
+use oort_api::prelude::*;
+use std::collections::VecDeque;
+
+// How close we need to get to a waypoint to have reached it, by default.
+pub const ARRIVAL_TOLERANCE: f64 = 50.;
+
+// Below this speed we count as stopped at a waypoint.
+const STOPPED_SPEED: f64 = 5.;
+
+// What to do on reaching a waypoint.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Arrival {
+    // Come to a stop, then move on.
+    Stop,
+    // Pass through at speed and carry on to the next waypoint.
+    FlyThrough,
+    // Come to a stop and hold position for this many seconds. Use
+    // f64::INFINITY to hold station indefinitely.
+    Loiter(f64),
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct Waypoint {
+    pub pos: Vec2,
+    pub arrival: Arrival,
+    // How close we need to get to count as having arrived.
+    pub tolerance: f64,
+    // The fastest we'll fly on the way here.
+    pub max_speed: f64,
+}
+
+impl Waypoint {
+    pub fn stop(pos: Vec2) -> Waypoint {
+        Waypoint {
+            pos,
+            arrival: Arrival::Stop,
+            tolerance: ARRIVAL_TOLERANCE,
+            max_speed: f64::INFINITY,
+        }
+    }
+
+    pub fn fly_through(pos: Vec2) -> Waypoint {
+        Waypoint {
+            arrival: Arrival::FlyThrough,
+            ..Waypoint::stop(pos)
+        }
+    }
+
+    pub fn loiter(pos: Vec2, duration: f64) -> Waypoint {
+        Waypoint {
+            arrival: Arrival::Loiter(duration),
+            ..Waypoint::stop(pos)
+        }
+    }
+}
+
+pub struct Navigator {
+    waypoints: VecDeque<Waypoint>,
+    // Whether waypoints go back on the end of the route once reached, so
+    // that we fly the route in a loop.
+    patrol: bool,
+    // When we stopped at the current waypoint, if we're loitering there.
+    arrived_at: Option<f64>,
+    // Where we last stopped. We keep station there once the route is done,
+    // rather than drift off at whatever speed counted as stopped.
+    station: Option<Vec2>,
+}
+
+impl Default for Navigator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Navigator {
+    // A navigator with nowhere to go.
+    pub fn new() -> Navigator {
+        Navigator {
+            waypoints: VecDeque::new(),
+            patrol: false,
+            arrived_at: None,
+            station: None,
+        }
+    }
+
+    // A navigator that flies the given route over and over.
+    pub fn patrol(route: Vec<Waypoint>) -> Navigator {
+        Navigator {
+            waypoints: route.into(),
+            patrol: true,
+            arrived_at: None,
+            station: None,
+        }
+    }
+
+    // Adds a waypoint to the end of the route.
+    pub fn push(&mut self, waypoint: Waypoint) {
+        self.waypoints.push_back(waypoint);
+    }
+
+    pub fn clear(&mut self) {
+        self.waypoints.clear();
+        self.arrived_at = None;
+        self.station = None;
+    }
+
+    // The waypoint we're heading for.
+    pub fn current(&self) -> Option<&Waypoint> {
+        self.waypoints.front()
+    }
+
+    // Whether we've been to every waypoint. A patrol is never done.
+    pub fn is_done(&self) -> bool {
+        self.waypoints.is_empty()
+    }
+
+    // Flies toward the current waypoint, moving on to the next when it's been
+    // reached. Returns false once the route is done, after which we hold
+    // position at the last waypoint we stopped at, if any.
+    pub fn tick(&mut self) -> bool {
+        // Several waypoints may be reached at once, but stop after a full lap
+        // of a patrol so that a route we're already at the end of doesn't
+        // spin forever.
+        for _ in 0..self.waypoints.len() {
+            if !self.reached_current() {
+                break;
+            }
+            self.advance();
+        }
+        let Some(&waypoint) = self.current() else {
+            if let Some(station) = self.station {
+                fly_to(station, vec2(0., 0.), f64::INFINITY);
+            }
+            return false;
+        };
+        let zero = vec2(0., 0.);
+        match waypoint.arrival {
+            Arrival::Stop | Arrival::Loiter(_) => {
+                fly_to(waypoint.pos, zero, waypoint.max_speed);
+            }
+            Arrival::FlyThrough => {
+                // Go as fast as we can while still being able to stop at the
+                // waypoint after, so that we aren't going too fast to follow
+                // the route when we get there.
+                let offset = waypoint.pos - position();
+                let beyond = self
+                    .waypoints
+                    .get(1)
+                    .map_or(f64::INFINITY, |next| (next.pos - waypoint.pos).length());
+                let speed = stopping_speed(offset.length() + beyond, braking_acceleration())
+                    .min(waypoint.max_speed);
+                if offset.length() > 0. {
+                    match_velocity(offset.normalize() * speed);
+                }
+            }
+        }
+        draw_line(position(), waypoint.pos, 0x00ff00);
+        true
+    }
+
+    fn reached_current(&mut self) -> bool {
+        let Some(waypoint) = self.current() else {
+            return false;
+        };
+        let offset = waypoint.pos - position();
+        match waypoint.arrival {
+            Arrival::Stop => {
+                offset.length() < waypoint.tolerance && velocity().length() < STOPPED_SPEED
+            }
+            Arrival::FlyThrough => {
+                // We move a long way in a tick when flying fast, so also count
+                // passing within tolerance before the next one.
+                let v = velocity();
+                let t = if v.dot(v) > 0. {
+                    (offset.dot(v) / v.dot(v)).clamp(0., TICK_LENGTH)
+                } else {
+                    0.
+                };
+                (offset - v * t).length() < waypoint.tolerance
+            }
+            Arrival::Loiter(duration) => {
+                if self.arrived_at.is_none()
+                    && offset.length() < waypoint.tolerance
+                    && velocity().length() < STOPPED_SPEED
+                {
+                    self.arrived_at = Some(current_time());
+                }
+                self.arrived_at
+                    .is_some_and(|t| current_time() - t >= duration)
+            }
+        }
+    }
+
+    fn advance(&mut self) {
+        self.arrived_at = None;
+        let Some(waypoint) = self.waypoints.pop_front() else {
+            return;
+        };
+        self.station = match waypoint.arrival {
+            Arrival::Stop | Arrival::Loiter(_) => Some(waypoint.pos),
+            Arrival::FlyThrough => None,
+        };
+        if self.patrol {
+            self.waypoints.push_back(waypoint);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{sim::Sim, test_env::*};
+
+    fn ticks(seconds: f64) -> u32 {
+        (seconds / TICK_LENGTH) as u32
+    }
+
+    #[test]
+    fn stops_at_each_waypoint_in_turn() {
+        let mut sim = Sim::new(Class::Fighter);
+        let mut navigator = Navigator::new();
+        navigator.push(Waypoint::stop(vec2(1000., 0.)));
+        navigator.push(Waypoint::stop(vec2(1000., 1000.)));
+
+        // Stops at the first on the way to the second.
+        let mut stopped_at_first = false;
+        for _ in 0..ticks(40.) {
+            sim.step(|| {
+                navigator.tick();
+            });
+            stopped_at_first |= (sim.ship.pos - vec2(1000., 0.)).length() < ARRIVAL_TOLERANCE
+                && sim.ship.vel.length() < STOPPED_SPEED;
+        }
+        assert!(stopped_at_first);
+        assert!(navigator.is_done());
+        assert!((sim.ship.pos - vec2(1000., 1000.)).length() < ARRIVAL_TOLERANCE);
+        assert!(sim.ship.vel.length() < STOPPED_SPEED);
+    }
+
+    #[test]
+    fn flies_through_without_stopping() {
+        let mut sim = Sim::new(Class::Fighter);
+        let mut navigator = Navigator::new();
+        navigator.push(Waypoint::fly_through(vec2(1000., 0.)));
+        navigator.push(Waypoint::stop(vec2(3000., 0.)));
+        let passing_speed = std::cell::Cell::new(0.);
+        sim.run_until(
+            ticks(30.),
+            || {
+                navigator.tick();
+            },
+            |sim| {
+                if sim.ship.pos.x < 1000. {
+                    passing_speed.set(sim.ship.vel.length());
+                }
+                sim.ship.pos.x >= 1000.
+            },
+        );
+        assert!(passing_speed.get() > 100., "{}", passing_speed.get());
+        assert_eq!(navigator.current().unwrap().pos, vec2(3000., 0.));
+    }
+
+    #[test]
+    fn loiters_for_duration() {
+        let mut sim = Sim::new(Class::Fighter);
+        let mut navigator = Navigator::new();
+        navigator.push(Waypoint::loiter(vec2(200., 0.), 5.));
+        navigator.push(Waypoint::stop(vec2(200., 1000.)));
+        let arrived = sim
+            .run_until(
+                ticks(30.),
+                || {
+                    navigator.tick();
+                },
+                |sim| sim.ship.vel.length() < STOPPED_SPEED && sim.ship.pos.x > 150.,
+            )
+            .unwrap();
+        // Held there for the whole 5 s before moving on.
+        sim.run_until(
+            ticks(4.5),
+            || {
+                navigator.tick();
+            },
+            |_| false,
+        );
+        assert!((sim.ship.pos - vec2(200., 0.)).length() < ARRIVAL_TOLERANCE);
+        let left = sim
+            .run_until(
+                ticks(30.),
+                || {
+                    navigator.tick();
+                },
+                |sim| sim.ship.pos.y > ARRIVAL_TOLERANCE,
+            )
+            .unwrap();
+        assert!(left < ticks(4.), "arrived {} left {}", arrived, left);
+    }
+
+    #[test]
+    fn patrol_loops_and_respects_speed_cap() {
+        let mut sim = Sim::new(Class::Fighter);
+        let corners = [vec2(500., 0.), vec2(500., 500.), vec2(0., 500.), vec2(0., 0.)];
+        let mut navigator = Navigator::patrol(
+            corners
+                .iter()
+                .map(|&pos| Waypoint {
+                    max_speed: 80.,
+                    ..Waypoint::fly_through(pos)
+                })
+                .collect(),
+        );
+        let mut top_speed = 0f64;
+        let mut visits = 0;
+        for _ in 0..ticks(60.) {
+            let before = navigator.current().unwrap().pos;
+            sim.step(|| {
+                navigator.tick();
+            });
+            if navigator.current().unwrap().pos != before {
+                visits += 1;
+            }
+            top_speed = top_speed.max(sim.ship.vel.length());
+        }
+        assert!(!navigator.is_done());
+        // Each side takes a little over 6 s at 80 m/s, so we get round at
+        // least twice.
+        assert!(visits >= 8, "{}", visits);
+        assert!(top_speed <= 80. + 1e-6, "{}", top_speed);
+    }
+
+    #[test]
+    fn empty_route_commands_nothing() {
+        let env = Env::new(Class::Fighter);
+        let mut navigator = Navigator::new();
+        assert!(!navigator.tick());
+        assert_eq!(env.acceleration(), vec2(0., 0.));
+    }
+}
@@ -0,0 +1,175 @@
+// Circling a contact at a distance.
+//
+// Keeping moving across the contact's line of sight makes us harder to lead,
+// and picking the distance lets us play our guns' range against theirs: a
+// frigate can sit out of a fighter's reach and still hit it. Like the rest of
+// our flight control this only calls accelerate(), so the helm stays free to
+// point the guns, e.g. with turn_and_shoot_at.
+use crate::{
+    contacts::Contact,
+    control::pos_after,
+    flight::{braking_acceleration, match_velocity, stopping_speed},
+    weapons::gun_range,
+};
+use oort_api::prelude::*;
+
+// How far inside our gun range, or outside theirs, a standoff keeps us.
+const STANDOFF_MARGIN: f64 = 500.;
+
+// How wide a standoff band is when the contact outranges us.
+const STANDOFF_BAND: f64 = 1000.;
+
+// The closest a standoff band can start, for ships with little or no gun
+// range of their own.
+const MIN_STANDOFF_RANGE: f64 = 1000.;
+
+pub struct Orbit {
+    // We keep within this band of distances from the contact.
+    pub min_range: f64,
+    pub max_range: f64,
+    // How fast we circle the contact.
+    pub speed: f64,
+    pub clockwise: bool,
+}
+
+impl Orbit {
+    pub fn new(min_range: f64, max_range: f64) -> Orbit {
+        Orbit {
+            min_range,
+            max_range,
+            speed: 150.,
+            clockwise: false,
+        }
+    }
+
+    // An orbit for a ship of class ours against a contact of class theirs
+    // that keeps us within reach of our guns and, if we can, out of reach of
+    // theirs. If they outrange us we stay near the limit of our own range.
+    // Without guns to speak of we keep a band just outside MIN_STANDOFF_RANGE.
+    pub fn standoff(ours: Class, theirs: Class) -> Orbit {
+        let max_range =
+            (gun_range(ours) - STANDOFF_MARGIN).max(MIN_STANDOFF_RANGE + STANDOFF_BAND);
+        let min_range = (gun_range(theirs) + STANDOFF_MARGIN)
+            .clamp(MIN_STANDOFF_RANGE, max_range - STANDOFF_BAND);
+        Orbit::new(min_range, max_range)
+    }
+
+    // Flies around the contact, moving back into the band if we're outside
+    // it. Returns the acceleration commanded.
+    pub fn tick(&self, contact: &Contact) -> Vec2 {
+        // Where the contact will be next tick, which is when the velocity we
+        // command takes effect.
+        let center = pos_after(contact.pos(), contact.vel(), contact.acc(), TICK_LENGTH);
+        let center_vel = contact.vel() + contact.acc() * TICK_LENGTH;
+        let offset = position() - center;
+        let range = offset.length();
+        let out = if range > 0. {
+            offset / range
+        } else {
+            vec2(1., 0.)
+        };
+        let around = if self.clockwise {
+            vec2(out.y, -out.x)
+        } else {
+            vec2(-out.y, out.x)
+        };
+
+        // Come back to the band as quickly as we can without overshooting
+        // it.
+        let braking = braking_acceleration();
+        let radial_speed = if range < self.min_range {
+            stopping_speed(self.min_range - range, braking)
+        } else if range > self.max_range {
+            -stopping_speed(range - self.max_range, braking)
+        } else {
+            0.
+        };
+        draw_line(position(), center, 0x00ffff);
+        match_velocity(center_vel + out * radial_speed + around * self.speed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        contacts::TrackPolicy,
+        control::{FIGHTER_GUN, Helm, turn_and_shoot_at},
+        sim::Sim,
+        test_env::*,
+    };
+
+    fn contact(pos: Vec2, vel: Vec2) -> Contact {
+        Contact::new(Class::Fighter, 0, pos, vel, 30., TrackPolicy::default())
+    }
+
+    #[test]
+    fn standoff_plays_range_against_range() {
+        // A frigate can stay out of a fighter's reach.
+        let orbit = Orbit::standoff(Class::Frigate, Class::Fighter);
+        assert!(orbit.min_range > gun_range(Class::Fighter));
+        assert!(orbit.max_range < gun_range(Class::Frigate));
+        // A fighter can't, so it stays within its own.
+        let orbit = Orbit::standoff(Class::Fighter, Class::Frigate);
+        assert!(orbit.min_range < orbit.max_range);
+        assert!(orbit.max_range < gun_range(Class::Fighter));
+    }
+
+    #[test]
+    fn standoff_without_guns_is_a_valid_band() {
+        for theirs in [Class::Fighter, Class::Missile] {
+            let orbit = Orbit::standoff(Class::Missile, theirs);
+            assert!(orbit.min_range >= MIN_STANDOFF_RANGE, "{}", orbit.min_range);
+            assert!(orbit.min_range < orbit.max_range);
+        }
+    }
+
+    #[test]
+    fn circles_in_the_band() {
+        let _env = Env::new(Class::Fighter);
+        let target = contact(vec2(1000., 0.), vec2(0., 0.));
+        let acc = Orbit::new(800., 1200.).tick(&target);
+        // Counterclockwise about the contact, i.e. down from where we are.
+        assert!(acc.y < 0. && acc.x.abs() < 1e-6, "{:?}", acc);
+        let mut orbit = Orbit::new(800., 1200.);
+        orbit.clockwise = true;
+        assert!(orbit.tick(&target).y > 0.);
+    }
+
+    #[test]
+    fn closes_in_from_outside_the_band() {
+        let _env = Env::new(Class::Fighter);
+        let target = contact(vec2(5000., 0.), vec2(0., 0.));
+        let acc = Orbit::new(800., 1200.).tick(&target);
+        assert!(acc.x > 0., "{:?}", acc);
+    }
+
+    #[test]
+    fn strafes_moving_target_while_shooting() {
+        let mut sim = Sim::new(Class::Fighter);
+        sim.add_target(Class::Fighter, vec2(3000., 0.), vec2(0., 50.));
+        let orbit = Orbit::new(1000., 1500.);
+        let mut helm = Helm::new();
+        let mut ranges = Vec::new();
+        let mut bearings = Vec::new();
+        for _ in 0..(60. / TICK_LENGTH) as u32 {
+            let (pos, vel) = (sim.targets[0].pos, sim.targets[0].vel);
+            sim.step(|| {
+                orbit.tick(&contact(pos, vel));
+                turn_and_shoot_at(&mut helm, pos, vel, vec2(0., 0.), &FIGHTER_GUN);
+            });
+            if sim.time() > 20. {
+                let offset = sim.ship.pos - sim.targets[0].pos;
+                ranges.push(offset.length());
+                bearings.push(offset.angle());
+            }
+        }
+        for range in &ranges {
+            assert!(*range > 950. && *range < 1550., "{}", range);
+        }
+        // We went a good way around the target.
+        let swept: f64 = bearings.windows(2).map(|w| angle_diff(w[0], w[1])).sum();
+        assert!(swept > PI / 2., "{}", swept);
+        assert!(sim.all_targets_destroyed());
+    }
+}
@@ -1,10 +1,5 @@
-use std::{ops::Mul, rc::Rc};
-
 use crate::{contacts::Contacts, control::*};
-use oort_api::{
-    ClassStats,
-    prelude::{maths_rs::*, *},
-};
+use oort_api::prelude::{maths_rs::*, *};
 
 pub struct Radar {
     update_contact_id: Option<u32>,
@@ -12,7 +7,7 @@ pub struct Radar {
     pub scan_beam_width: f64,
 }
 
-fn ship_dim(c: Class) -> f64 {
+pub fn ship_dim(c: Class) -> f64 {
     match c {
         Class::Fighter => 20.,
         Class::Frigate => 240.,
@@ -25,6 +20,12 @@ fn ship_dim(c: Class) -> f64 {
     }
 }
 
+impl Default for Radar {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl Radar {
     pub fn new() -> Radar {
         Radar {
@@ -44,14 +45,14 @@ impl Radar {
 
     pub fn tick(&mut self, contacts: &mut Contacts) {
         {
-            match (self.update_contact_id, scan()) {
-                (Some(id), scan_result) => {
-                    contacts.update(id, scan_result);
+            let scan_results: Vec<ScanResult> = scan().into_iter().collect();
+            match self.update_contact_id {
+                Some(id) => {
+                    contacts.update(id, scan_results.into_iter().next());
                 }
-                (None, Some(scan_result)) => {
-                    contacts.recv_contact(scan_result);
+                None => {
+                    contacts.recv_contacts(scan_results);
                 }
-                (None, None) => {}
             }
             self.update_contact_id = None;
         }
@@ -96,3 +97,63 @@ impl Radar {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_env::*;
+
+    #[test]
+    fn sweeps_when_nothing_to_track() {
+        let mut env = Env::new(Class::Fighter);
+        let mut radar = Radar::new();
+        let mut contacts = Contacts::new();
+        radar.tick(&mut contacts);
+        assert_eq!(radar_heading(), radar.scan_beam_width);
+        assert_eq!(radar_width(), radar.scan_beam_width);
+        env.advance_ticks(1);
+        radar.tick(&mut contacts);
+        assert_eq!(radar_heading(), 2. * radar.scan_beam_width);
+    }
+
+    #[test]
+    fn search_scan_creates_contact() {
+        let mut env = Env::new(Class::Fighter);
+        let mut radar = Radar::new();
+        let mut contacts = Contacts::new();
+        env.set_scan(Some(scan_result(
+            Class::Fighter,
+            vec2(1000., 1000.),
+            vec2(0., 0.),
+        )));
+        radar.tick(&mut contacts);
+        assert_eq!(contacts.iter().count(), 1);
+    }
+
+    #[test]
+    fn points_at_stale_contact() {
+        let mut env = Env::new(Class::Fighter);
+        let mut radar = Radar::new();
+        let mut contacts = Contacts::new();
+        contacts.recv_contact(scan_result(
+            Class::Fighter,
+            vec2(1000., 1000.),
+            vec2(0., 0.),
+        ));
+        env.advance_ticks(10);
+        contacts.tick();
+        radar.tick(&mut contacts);
+        assert!((radar_heading() - PI / 4.).abs() < 1e-3, "{}", radar_heading());
+        let dist = vec2(1000., 1000.).length();
+        assert!(radar_min_distance() < dist && dist < radar_max_distance());
+
+        // The radar doesn't find it where we looked.
+        env.advance_ticks(1);
+        env.set_scan(None);
+        contacts.tick();
+        radar.tick(&mut contacts);
+        let c = contacts.iter().next().unwrap();
+        assert_eq!(c.since_update_ticks(), 11);
+        assert_eq!(c.state(), crate::contacts::TrackState::Tentative);
+    }
+}
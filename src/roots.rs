@@ -0,0 +1,221 @@
+// Closed-form real roots of low order polynomials.
+//
+// Each solver takes the coefficients from the highest power down and returns
+// the real roots in ascending order, with repeated roots listed once per
+// multiplicity that the method distinguishes. A leading coefficient that is
+// negligible compared to the others is treated as zero and the polynomial is
+// solved as one of lower degree: the root that is dropped is enormous and of
+// no use to us. Callers get the best results when the coefficients have been
+// scaled to be roughly of the same magnitude.
+//
+// A quartic whose leading coefficient is small but not negligible has roots
+// of very different magnitudes, and Ferrari's method loses the small ones.
+// Such a quartic is solved for 1/x instead, which swaps the roles of the
+// small and the large roots.
+use std::f64::consts::PI;
+
+// Relative size below which a leading coefficient is considered zero.
+const NEGLIGIBLE: f64 = 1e-12;
+
+fn negligible(leading: f64, rest: &[f64]) -> bool {
+    let scale = rest.iter().fold(0f64, |m, c| m.max(c.abs()));
+    leading.abs() <= NEGLIGIBLE * scale
+}
+
+// Solves a x^2 + b x + c = 0.
+pub fn quadratic(a: f64, b: f64, c: f64) -> Vec<f64> {
+    if negligible(a, &[b, c]) {
+        if b == 0. {
+            return Vec::new();
+        }
+        return vec![-c / b];
+    }
+    let disc = b * b - 4. * a * c;
+    if disc < 0. {
+        return Vec::new();
+    }
+    // Avoid the cancellation in -b + sqrt(disc) when b is large by computing
+    // the larger magnitude root first and deriving the other from the product
+    // of the roots.
+    let q = -0.5 * (b + b.signum() * disc.sqrt());
+    if q == 0. {
+        return vec![0., 0.];
+    }
+    let mut roots = vec![q / a, c / q];
+    roots.sort_by(f64::total_cmp);
+    roots
+}
+
+// Solves a x^3 + b x^2 + c x + d = 0 with Cardano's method, or the
+// trigonometric method when there are three real roots.
+pub fn cubic(a: f64, b: f64, c: f64, d: f64) -> Vec<f64> {
+    if negligible(a, &[b, c, d]) {
+        return quadratic(b, c, d);
+    }
+    let (b, c, d) = (b / a, c / a, d / a);
+    // Substitute x = t - b/3 to get t^3 + p t + q = 0.
+    let shift = -b / 3.;
+    let p = c - b * b / 3.;
+    let q = 2. * b * b * b / 27. - b * c / 3. + d;
+    let disc = (q / 2.).powi(2) + (p / 3.).powi(3);
+    let mut roots = if disc > 0. {
+        let s = disc.sqrt();
+        vec![(-q / 2. + s).cbrt() + (-q / 2. - s).cbrt()]
+    } else if p == 0. {
+        vec![0.]
+    } else {
+        let r = 2. * (-p / 3.).sqrt();
+        let phi = ((3. * q / (2. * p)) * (-3. / p).sqrt())
+            .clamp(-1., 1.)
+            .acos()
+            / 3.;
+        (0..3)
+            .map(|k| r * (phi - 2. * PI * k as f64 / 3.).cos())
+            .collect()
+    };
+    for t in &mut roots {
+        *t += shift;
+    }
+    roots.sort_by(f64::total_cmp);
+    roots
+}
+
+// Solves a x^4 + b x^3 + c x^2 + d x + e = 0 with Ferrari's method.
+pub fn quartic(a: f64, b: f64, c: f64, d: f64, e: f64) -> Vec<f64> {
+    if negligible(a, &[b, c, d, e]) {
+        return cubic(b, c, d, e);
+    }
+    if a.abs() < e.abs() {
+        // e y^4 + d y^3 + c y^2 + b y + a = 0 with y = 1/x. No root is zero
+        // since a isn't, and the reversed quartic won't be reversed again.
+        let mut roots: Vec<f64> = quartic(e, d, c, b, a)
+            .into_iter()
+            .map(|y| polish(1. / y, [1., b / a, c / a, d / a, e / a]))
+            .collect();
+        roots.sort_by(f64::total_cmp);
+        return roots;
+    }
+    let (b, c, d, e) = (b / a, c / a, d / a, e / a);
+    // Substitute x = y - b/4 to get y^4 + p y^2 + q y + r = 0.
+    let shift = -b / 4.;
+    let p = c - 3. * b * b / 8.;
+    let q = d - b * c / 2. + b * b * b / 8.;
+    let r = e - b * d / 4. + b * b * c / 16. - 3. * b.powi(4) / 256.;
+
+    // With m a positive root of the resolvent cubic the quartic factors into
+    // two quadratics:
+    //   y^2 - s y + (p/2 + m + q/2s) and y^2 + s y + (p/2 + m - q/2s)
+    // where s = sqrt(2m). If there is no such root then q is zero and the
+    // quartic is a quadratic in y^2.
+    let m = cubic(1., p, p * p / 4. - r, -q * q / 8.)
+        .last()
+        .copied()
+        .unwrap_or(0.);
+    let mut roots = if m > 0. {
+        let s = (2. * m).sqrt();
+        let mut roots = quadratic(1., -s, p / 2. + m + q / (2. * s));
+        roots.extend(quadratic(1., s, p / 2. + m - q / (2. * s)));
+        roots
+    } else {
+        quadratic(1., p, r)
+            .into_iter()
+            .filter(|&z| z >= 0.)
+            .flat_map(|z| [-z.sqrt(), z.sqrt()])
+            .collect()
+    };
+    for y in &mut roots {
+        *y += shift;
+        *y = polish(*y, [1., b, c, d, e]);
+    }
+    roots.sort_by(f64::total_cmp);
+    roots
+}
+
+// Ferrari's method loses precision in the resolvent cubic. A few Newton steps
+// on the original polynomial recover it.
+fn polish(mut x: f64, coeffs: [f64; 5]) -> f64 {
+    for _ in 0..3 {
+        let (f, df) = coeffs
+            .iter()
+            .fold((0., 0.), |(f, df), &c| (f * x + c, df * x + f));
+        if df == 0. {
+            break;
+        }
+        let next = x - f / df;
+        if !next.is_finite() {
+            break;
+        }
+        x = next;
+    }
+    x
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_roots(actual: Vec<f64>, expected: &[f64]) {
+        assert_eq!(actual.len(), expected.len(), "{:?}", actual);
+        for (a, e) in actual.iter().zip(expected) {
+            assert!((a - e).abs() < 1e-6, "{:?} != {:?}", actual, expected);
+        }
+    }
+
+    #[test]
+    fn quadratic_roots() {
+        assert_roots(quadratic(1., -3., 2.), &[1., 2.]);
+        assert_roots(quadratic(1., 0., 1.), &[]);
+        assert_roots(quadratic(0., 2., -4.), &[2.]);
+        // Large b, where the textbook formula cancels.
+        assert_roots(quadratic(1., -1e8, 1.), &[1e-8, 1e8]);
+    }
+
+    #[test]
+    fn cubic_roots() {
+        assert_roots(cubic(1., -6., 11., -6.), &[1., 2., 3.]);
+        assert_roots(cubic(2., 0., 0., -16.), &[2.]);
+        assert_roots(cubic(1., -3., 3., -1.).into_iter().take(1).collect(), &[1.]);
+    }
+
+    #[test]
+    fn quartic_roots() {
+        // (x - 1)(x - 2)(x - 3)(x - 4)
+        assert_roots(quartic(1., -10., 35., -50., 24.), &[1., 2., 3., 4.]);
+        // (x^2 - 1)(x^2 - 4), which has no cubic or linear term.
+        assert_roots(quartic(1., 0., -5., 0., 4.), &[-2., -1., 1., 2.]);
+        // (x^2 + 1)(x - 1)(x - 3)
+        assert_roots(quartic(1., -4., 4., -4., 3.), &[1., 3.]);
+        assert_roots(quartic(1., 0., 1., 0., 1.), &[]);
+    }
+
+    #[test]
+    fn negligible_leading_coefficient_lowers_degree() {
+        assert_roots(quartic(1e-20, 1., -6., 11., -6.), &[1., 2., 3.]);
+    }
+
+    #[test]
+    fn small_leading_coefficient_keeps_small_roots() {
+        // The intercept quartic for a barely accelerating target. Besides
+        // the roots near 1 it has two near +-9e4, where the target finally
+        // outruns the bullet. Those were the only ones Ferrari's method used
+        // to find.
+        let (a, b, c, d, e) = (1.228e-10, 1.097e-6, -0.9975, -0.0712, 1.0);
+        let roots = quartic(a, b, c, d, e);
+        assert_eq!(roots.len(), 4, "{:?}", roots);
+        for &x in &roots {
+            let f = (((a * x + b) * x + c) * x + d) * x + e;
+            let scale = (((a * x.abs() + b.abs()) * x.abs() + c.abs()) * x.abs() + d.abs())
+                * x.abs()
+                + e.abs();
+            assert!(f.abs() < 1e-9 * scale, "{:?}", roots);
+        }
+        // Without the tiny higher order terms the small roots are those of
+        // a quadratic.
+        let small: Vec<f64> = roots.into_iter().filter(|x| x.abs() < 10.).collect();
+        let expected = quadratic(c, d, e);
+        assert_eq!(small.len(), expected.len(), "{:?}", small);
+        for (x, y) in small.iter().zip(&expected) {
+            assert!((x - y).abs() < 1e-5, "{:?} {:?}", small, expected);
+        }
+    }
+}
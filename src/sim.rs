@@ -0,0 +1,470 @@
+// A small deterministic 2D simulator for closed-loop scenario tests.
+//
+// The game runs our Ship::tick once per tick against the state it writes into
+// oort_api's globals. The simulator does the same natively: it writes our
+// ship's state and a radar return, runs the AI, reads back the commands it
+// issued and integrates the world forward one tick. That lets tests check
+// things like "the tutorial_lead ship kills its target within 30 seconds"
+// without uploading anything to the game.
+//
+// Only one ship is controlled by the AI under test. Everything else is a
+// scripted target that moves with a constant (but test-adjustable)
+// acceleration and can be destroyed by our bullets, or by our ship exploding
+// next to it if it's a missile. The physics are a
+// simplification of the game's: no collisions between ships, no drag, a
+// single forward gun and a made-up radar noise model.
+//
+// Built for tests and behind the "sim" feature for anything else that wants
+// to drive it.
+use crate::{control::FIGHTER_GUN, radar::ship_dim, test_env::Env};
+use oort_api::prelude::{oorandom::Rand64, *};
+
+// The gun on our ship. It defaults to a fighter's forward gun.
+pub struct SimGun {
+    pub speed: f64,
+    pub reload_ticks: u32,
+    // How long a bullet flies before it disappears.
+    pub lifetime: f64,
+    // How far in front of the ship's center bullets are spawned.
+    pub offset: f64,
+    pub damage: f64,
+}
+
+impl Default for SimGun {
+    fn default() -> Self {
+        SimGun {
+            speed: FIGHTER_GUN.speed,
+            reload_ticks: 4,
+            lifetime: FIGHTER_GUN.lifetime,
+            offset: FIGHTER_GUN.offset.x,
+            damage: 20.,
+        }
+    }
+}
+
+// How the radar reports targets to the AI.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RadarModel {
+    // The first live target is always reported exactly, wherever the radar
+    // points. This is how the early tutorials hand out target() and
+    // target_velocity().
+    Perfect,
+    // Only targets inside the beam are seen and the strongest return is
+    // reported with noise that grows with distance and beam width.
+    Noisy,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct Body {
+    pub class: Class,
+    pub pos: Vec2,
+    pub vel: Vec2,
+    // For targets, the scripted acceleration. For our ship, the acceleration
+    // commanded on the last tick.
+    pub acc: Vec2,
+    pub heading: f64,
+    pub angular_velocity: f64,
+    pub health: f64,
+}
+
+impl Body {
+    fn new(class: Class, pos: Vec2, vel: Vec2) -> Body {
+        Body {
+            class,
+            pos,
+            vel,
+            acc: vec2(0., 0.),
+            heading: 0.,
+            angular_velocity: 0.,
+            health: class.default_stats().max_health,
+        }
+    }
+
+    pub fn is_alive(&self) -> bool {
+        self.health > 0.
+    }
+
+    fn radius(&self) -> f64 {
+        ship_dim(self.class) / 2.
+    }
+}
+
+struct Bullet {
+    pos: Vec2,
+    vel: Vec2,
+    expires: f64,
+}
+
+// The SNR of a return from 1 km away with a beam of REFERENCE_BEAM_WIDTH. It
+// falls off by 20 dB per decade of distance and rises as the beam narrows.
+const REFERENCE_SNR: f64 = 40.;
+const REFERENCE_BEAM_WIDTH: f64 = PI / 8.;
+
+// The standard deviation of the noise on a return's position and velocity in
+// each axis, at 0 dB SNR. It falls by a factor of 10 for every 10 dB.
+//
+// We can't see how the game adds its noise, so this is our own guess, and it
+// deliberately isn't the range and bearing model in contacts.rs: scenario
+// tests would learn nothing from a tracker that is fed exactly the noise it
+// assumes. Drawing the error independently in x and y matches the tracker
+// along the beam and is rougher than it expects across the beam at close
+// range.
+const POSITION_NOISE: f64 = 1e3;
+const VELOCITY_NOISE: f64 = 1e2;
+
+pub struct Sim {
+    env: Env,
+    pub ship: Body,
+    pub targets: Vec<Body>,
+    pub gun: SimGun,
+    pub radar_model: RadarModel,
+    // Targets within this distance of our ship when it explodes are
+    // destroyed.
+    pub blast_radius: f64,
+    // Where our ship exploded, if it has.
+    pub exploded_at: Option<Vec2>,
+    bullets: Vec<Bullet>,
+    reload_ticks: u32,
+    tick: u32,
+    rng: Rand64,
+}
+
+impl Sim {
+    // Creates a world with our ship of the given class sitting still at the
+    // origin and no targets.
+    pub fn new(class: Class) -> Sim {
+        Sim {
+            env: Env::new(class),
+            ship: Body::new(class, vec2(0., 0.), vec2(0., 0.)),
+            targets: Vec::new(),
+            gun: SimGun::default(),
+            radar_model: RadarModel::Perfect,
+            blast_radius: 30.,
+            exploded_at: None,
+            bullets: Vec::new(),
+            reload_ticks: 0,
+            tick: 0,
+            rng: Rand64::new(1234),
+        }
+    }
+
+    // Adds a target and returns its index in targets.
+    pub fn add_target(&mut self, class: Class, pos: Vec2, vel: Vec2) -> usize {
+        self.targets.push(Body::new(class, pos, vel));
+        self.targets.len() - 1
+    }
+
+    pub fn set_scenario_name(&mut self, name: &str) {
+        self.env.set_scenario_name(name);
+    }
+
+    pub fn time(&self) -> f64 {
+        self.tick as f64 * TICK_LENGTH
+    }
+
+    pub fn target_destroyed(&self, index: usize) -> bool {
+        !self.targets[index].is_alive()
+    }
+
+    pub fn all_targets_destroyed(&self) -> bool {
+        self.targets.iter().all(|t| !t.is_alive())
+    }
+
+    // Runs a single tick: presents the world to the AI, calls ai (which should
+    // run the ship's tick) and applies the commands it issued.
+    pub fn step(&mut self, ai: impl FnOnce()) {
+        self.env.set_tick(self.tick);
+        self.env.set_position(self.ship.pos);
+        self.env.set_velocity(self.ship.vel);
+        self.env.set_heading(self.ship.heading);
+        self.env.set_angular_velocity(self.ship.angular_velocity);
+        self.env.set_reload_ticks(0, self.reload_ticks);
+        let scan = self.scan();
+        self.env.set_scan(scan);
+
+        ai();
+
+        if self.env.exploded() {
+            self.explode();
+        }
+
+        let max_angular_acceleration = max_angular_acceleration();
+        let torque = self
+            .env
+            .torque()
+            .clamp(-max_angular_acceleration, max_angular_acceleration);
+        self.ship.acc = self.env.acceleration();
+        let fired = self.env.fired(0) && self.reload_ticks == 0;
+        self.reload_ticks = self.reload_ticks.saturating_sub(1);
+
+        self.ship.angular_velocity += torque * TICK_LENGTH;
+        self.ship.heading = (self.ship.heading + self.ship.angular_velocity * TICK_LENGTH)
+            .rem_euclid(2. * PI);
+        integrate(&mut self.ship);
+        for target in self.targets.iter_mut().filter(|t| t.is_alive()) {
+            integrate(target);
+        }
+        self.move_bullets();
+        // Bullets leave the gun at the end of the tick, once the ship has
+        // moved and turned.
+        if fired {
+            self.fire();
+        }
+
+        self.tick += 1;
+        self.env.advance_ticks(1);
+    }
+
+    // Steps until done returns true or max_ticks have elapsed. Returns the
+    // number of ticks it took, or None if we ran out of time.
+    pub fn run_until(
+        &mut self,
+        max_ticks: u32,
+        mut ai: impl FnMut(),
+        done: impl Fn(&Sim) -> bool,
+    ) -> Option<u32> {
+        for i in 0..max_ticks {
+            if done(self) {
+                return Some(i);
+            }
+            self.step(&mut ai);
+        }
+        done(self).then_some(max_ticks)
+    }
+
+    fn explode(&mut self) {
+        let pos = self.ship.pos;
+        for target in &mut self.targets {
+            if (target.pos - pos).length() < self.blast_radius {
+                target.health = 0.;
+            }
+        }
+        self.ship.health = 0.;
+        self.exploded_at = Some(pos);
+    }
+
+    fn fire(&mut self) {
+        let dir = vec2(1., 0.).rotate(self.ship.heading);
+        self.bullets.push(Bullet {
+            pos: self.ship.pos + dir * self.gun.offset,
+            vel: self.ship.vel + dir * self.gun.speed,
+            expires: self.time() + self.gun.lifetime,
+        });
+        self.reload_ticks = self.gun.reload_ticks;
+    }
+
+    // Moves the bullets and applies damage to the first target each one hits
+    // during the tick. Targets have already moved, so the test is done in the
+    // target's frame over the tick just elapsed.
+    fn move_bullets(&mut self) {
+        let now = self.time();
+        let damage = self.gun.damage;
+        let targets = &mut self.targets;
+        self.bullets.retain_mut(|bullet| {
+            for target in targets.iter_mut().filter(|t| t.is_alive()) {
+                let start = bullet.pos - (target.pos - target.vel * TICK_LENGTH);
+                let travel = (bullet.vel - target.vel) * TICK_LENGTH;
+                let s = (-start.dot(travel) / travel.dot(travel)).clamp(0., 1.);
+                if (start + travel * s).length() < target.radius() {
+                    target.health -= damage;
+                    return false;
+                }
+            }
+            bullet.pos += bullet.vel * TICK_LENGTH;
+            bullet.expires > now
+        });
+    }
+
+    // What the radar sees this tick, according to the radar model and the
+    // beam the AI set up on the previous tick.
+    fn scan(&mut self) -> Option<ScanResult> {
+        match self.radar_model {
+            RadarModel::Perfect => self.targets.iter().find(|t| t.is_alive()).map(|t| {
+                ScanResult {
+                    class: t.class,
+                    position: t.pos,
+                    velocity: t.vel,
+                    rssi: 0.,
+                    snr: f64::INFINITY,
+                }
+            }),
+            RadarModel::Noisy => {
+                let (heading, width) = (radar_heading(), radar_width());
+                let (min_distance, max_distance) = (radar_min_distance(), radar_max_distance());
+                let origin = self.ship.pos;
+                let target = self
+                    .targets
+                    .iter()
+                    .filter(|t| t.is_alive())
+                    .filter(|t| {
+                        let d = t.pos - origin;
+                        let range = d.length();
+                        range >= min_distance
+                            && range <= max_distance
+                            && angle_diff(heading, d.angle()).abs() <= width / 2.
+                    })
+                    .min_by(|a, b| {
+                        (a.pos - origin)
+                            .length()
+                            .total_cmp(&(b.pos - origin).length())
+                    })
+                    .copied()?;
+
+                let d = target.pos - origin;
+                let snr = REFERENCE_SNR - 20. * (d.length() / 1000.).log10()
+                    + 10. * (REFERENCE_BEAM_WIDTH / width).log10();
+                let error_factor = 10f64.powf(-snr / 10.);
+                let position = target.pos
+                    + vec2(self.gaussian(), self.gaussian()) * POSITION_NOISE * error_factor;
+                let velocity = target.vel
+                    + vec2(self.gaussian(), self.gaussian()) * VELOCITY_NOISE * error_factor;
+                Some(ScanResult {
+                    class: target.class,
+                    position,
+                    velocity,
+                    rssi: 0.,
+                    snr,
+                })
+            }
+        }
+    }
+
+    // A standard normal sample, using the Box-Muller transform.
+    fn gaussian(&mut self) -> f64 {
+        let u1 = 1. - self.rng.rand_float();
+        let u2 = self.rng.rand_float();
+        (-2. * u1.ln()).sqrt() * (2. * PI * u2).cos()
+    }
+}
+
+fn integrate(body: &mut Body) {
+    body.pos += body.vel * TICK_LENGTH + body.acc * (0.5 * TICK_LENGTH * TICK_LENGTH);
+    body.vel += body.acc * TICK_LENGTH;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        control::*, missile, tutorial_deflection, tutorial_lead, tutorial_rotation, tutorial_search,
+    };
+
+    const SECOND: u32 = 60;
+
+    #[test]
+    fn ship_integrates_commanded_acceleration() {
+        let mut sim = Sim::new(Class::Fighter);
+        for _ in 0..SECOND {
+            sim.step(|| accelerate(vec2(10., 0.)));
+        }
+        assert!((sim.ship.vel - vec2(10., 0.)).length() < 1e-9, "{:?}", sim.ship.vel);
+        assert!((sim.ship.pos.x - 5.).abs() < 0.1, "{:?}", sim.ship.pos);
+    }
+
+    #[test]
+    fn torque_is_limited() {
+        let mut sim = Sim::new(Class::Fighter);
+        sim.step(|| torque(1e6));
+        let max = Class::Fighter.default_stats().max_angular_acceleration;
+        assert!((sim.ship.angular_velocity - max * TICK_LENGTH).abs() < 1e-9);
+    }
+
+    #[test]
+    fn bullets_hit_target_ahead() {
+        let mut sim = Sim::new(Class::Fighter);
+        let target = sim.add_target(Class::Target, vec2(1000., 0.), vec2(0., 0.));
+        sim.step(|| fire(0));
+        let ticks = sim.run_until(2 * SECOND, || {}, |sim| sim.target_destroyed(target));
+        assert!(ticks.is_some());
+    }
+
+    #[test]
+    fn bullets_expire() {
+        let mut sim = Sim::new(Class::Fighter);
+        sim.gun.lifetime = 0.5;
+        let target = sim.add_target(Class::Target, vec2(1000., 0.), vec2(0., 0.));
+        sim.step(|| fire(0));
+        let ticks = sim.run_until(2 * SECOND, || {}, |sim| sim.target_destroyed(target));
+        assert_eq!(ticks, None);
+    }
+
+    #[test]
+    fn tutorial_rotation_kills_target() {
+        let mut sim = Sim::new(Class::Fighter);
+        sim.add_target(Class::Target, vec2(-800., 600.), vec2(0., 0.));
+        let mut ship = tutorial_rotation::Ship::new();
+        let ticks = sim.run_until(10 * SECOND, || ship.tick(), Sim::all_targets_destroyed);
+        assert!(ticks.is_some());
+    }
+
+    #[test]
+    fn tutorial_lead_kills_target_within_30_seconds() {
+        let mut sim = Sim::new(Class::Fighter);
+        sim.add_target(Class::Target, vec2(2000., 1000.), vec2(-50., 150.));
+        let mut ship = tutorial_lead::Ship::new();
+        let ticks = sim.run_until(30 * SECOND, || ship.tick(), Sim::all_targets_destroyed);
+        assert!(ticks.is_some());
+    }
+
+    #[test]
+    fn tutorial_deflection_kills_accelerating_target() {
+        let mut sim = Sim::new(Class::Fighter);
+        let target = sim.add_target(Class::Target, vec2(2000., -500.), vec2(0., 100.));
+        sim.targets[target].acc = vec2(-20., 10.);
+        let mut ship = tutorial_deflection::Ship::new();
+        let ticks = sim.run_until(30 * SECOND, || ship.tick(), Sim::all_targets_destroyed);
+        assert!(ticks.is_some());
+    }
+
+    #[test]
+    fn kills_target_while_strafing() {
+        let mut sim = Sim::new(Class::Fighter);
+        sim.ship.vel = vec2(0., -150.);
+        let index = sim.add_target(Class::Target, vec2(2500., 0.), vec2(-30., 80.));
+        let mut helm = Helm::new();
+        let ticks = sim.run_until(
+            30 * SECOND,
+            || {
+                // Weave left and right of our heading every couple of seconds.
+                let side = if (current_tick() / 120).is_multiple_of(2) {
+                    1.
+                } else {
+                    -1.
+                };
+                accelerate(vec2(0., side * 60.).rotate(heading()));
+                turn_and_shoot_at(
+                    &mut helm,
+                    target(),
+                    target_velocity(),
+                    vec2(0., 0.),
+                    &FIGHTER_GUN,
+                );
+            },
+            |sim| sim.target_destroyed(index),
+        );
+        assert!(ticks.is_some());
+    }
+
+    #[test]
+    fn missile_intercepts_maneuvering_fighter() {
+        let mut sim = Sim::new(Class::Missile);
+        sim.radar_model = RadarModel::Noisy;
+        sim.ship.vel = vec2(200., 0.);
+        let target = sim.add_target(Class::Fighter, vec2(3000., 800.), vec2(-50., 100.));
+        sim.targets[target].acc = vec2(0., -30.);
+        let mut ship = missile::Ship::new();
+        let ticks = sim.run_until(20 * SECOND, || ship.tick(), |sim| sim.exploded_at.is_some());
+        assert!(ticks.is_some());
+        assert!(sim.target_destroyed(target));
+    }
+
+    #[test]
+    fn tutorial_search_finds_and_kills_target() {
+        let mut sim = Sim::new(Class::Fighter);
+        sim.radar_model = RadarModel::Noisy;
+        sim.add_target(Class::Fighter, vec2(-1500., 2500.), vec2(40., -30.));
+        let mut ship = tutorial_search::Ship::new();
+        let ticks = sim.run_until(60 * SECOND, || ship.tick(), Sim::all_targets_destroyed);
+        assert!(ticks.is_some());
+    }
+}
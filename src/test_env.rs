@@ -0,0 +1,227 @@
+// A stand-in for the game when running unit tests natively. sim.rs builds a
+// full simulation on top of it.
+//
+// oort_api reads and writes all of its state through a global array that the
+// game fills in before each tick and reads back afterwards. Natively that
+// array is just a static, so tests can play the part of the game: set up our
+// ship's state, inject radar returns, advance the clock and read back the
+// commands the code under test issued.
+//
+// The state is global, so only one Env can exist at a time. Env::new blocks
+// until any other test using the environment has finished.
+//
+// Not every test needs every helper.
+#![allow(dead_code)]
+
+use oort_api::{
+    SystemState,
+    prelude::*,
+    rng_state::{self, RngState},
+    sys::{ENVIRONMENT, read_system_state, write_system_state},
+};
+use std::sync::{Mutex, MutexGuard};
+
+static ENV_LOCK: Mutex<()> = Mutex::new(());
+
+// The commands a ship can issue during a tick. These are cleared when the
+// environment advances to the next tick.
+const COMMANDS: [SystemState; 12] = [
+    SystemState::AccelerateX,
+    SystemState::AccelerateY,
+    SystemState::Torque,
+    SystemState::Aim0,
+    SystemState::Aim1,
+    SystemState::Aim2,
+    SystemState::Aim3,
+    SystemState::Fire0,
+    SystemState::Fire1,
+    SystemState::Fire2,
+    SystemState::Fire3,
+    SystemState::Explode,
+];
+
+pub struct Env {
+    _guard: MutexGuard<'static, ()>,
+}
+
+impl Env {
+    // Resets the environment to a ship of the given class sitting still at
+    // the origin at tick 0.
+    pub fn new(class: Class) -> Env {
+        // A test that panicked while holding the lock poisons it. The state
+        // is about to be reset anyway, so that's harmless.
+        let guard = ENV_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        for i in 0..SystemState::Size as u8 {
+            // SAFETY: SystemState is a fieldless enum with contiguous
+            // discriminants starting at 0 and Size is its last variant.
+            let index = unsafe { std::mem::transmute::<u8, SystemState>(i) };
+            write_system_state(index, 0.);
+        }
+        oort_api::dbg::reset();
+        // SAFETY: only one Env exists at a time, so nothing else is using
+        // the environment.
+        unsafe { (*std::ptr::addr_of_mut!(ENVIRONMENT))[0] = 0 };
+
+        let stats = class.default_stats();
+        write_system_state(SystemState::Class, class as u32 as f64);
+        write_system_state(SystemState::Seed, 1234.);
+        write_system_state(
+            SystemState::MaxForwardAcceleration,
+            stats.max_forward_acceleration,
+        );
+        write_system_state(
+            SystemState::MaxBackwardAcceleration,
+            stats.max_backward_acceleration,
+        );
+        write_system_state(
+            SystemState::MaxLateralAcceleration,
+            stats.max_lateral_acceleration,
+        );
+        write_system_state(
+            SystemState::MaxAngularAcceleration,
+            stats.max_angular_acceleration,
+        );
+        write_system_state(SystemState::Health, stats.max_health);
+        // SAFETY: only one Env exists at a time, so nothing else is using
+        // the RNG.
+        unsafe { rng_state::set(RngState::new()) };
+        Env { _guard: guard }
+    }
+
+    pub fn set_position(&mut self, pos: Vec2) {
+        write_system_state(SystemState::PositionX, pos.x);
+        write_system_state(SystemState::PositionY, pos.y);
+    }
+
+    pub fn set_velocity(&mut self, vel: Vec2) {
+        write_system_state(SystemState::VelocityX, vel.x);
+        write_system_state(SystemState::VelocityY, vel.y);
+    }
+
+    pub fn set_heading(&mut self, heading: f64) {
+        write_system_state(SystemState::Heading, heading);
+    }
+
+    pub fn set_angular_velocity(&mut self, angular_velocity: f64) {
+        write_system_state(SystemState::AngularVelocity, angular_velocity);
+    }
+
+    pub fn set_tick(&mut self, tick: u32) {
+        write_system_state(SystemState::CurrentTick, tick as f64);
+    }
+
+    // Sets the name scenario_name() returns.
+    pub fn set_scenario_name(&mut self, name: &str) {
+        let environment = format!("SCENARIO_NAME={name}\0");
+        // SAFETY: only one Env exists at a time, so nothing else is reading
+        // the environment.
+        unsafe {
+            (&mut *std::ptr::addr_of_mut!(ENVIRONMENT))[..environment.len()]
+                .copy_from_slice(environment.as_bytes())
+        };
+    }
+
+    pub fn set_reload_ticks(&mut self, index: usize, ticks: u32) {
+        let state = match index {
+            0 => SystemState::ReloadTicks0,
+            1 => SystemState::ReloadTicks1,
+            2 => SystemState::ReloadTicks2,
+            3 => SystemState::ReloadTicks3,
+            _ => return,
+        };
+        write_system_state(state, ticks as f64);
+    }
+
+    // Sets what the currently selected radar will return from scan(). None
+    // means the radar found nothing.
+    pub fn set_scan(&mut self, scan: Option<ScanResult>) {
+        let sel = read_system_state(SystemState::SelectedRadar) as usize;
+        let indices = radar_internal::radar_contact_indices(sel);
+        let Some(scan) = scan else {
+            write_system_state(indices.found, 0.);
+            return;
+        };
+        write_system_state(indices.found, 1.);
+        write_system_state(indices.class, scan.class as u32 as f64);
+        write_system_state(indices.position[0], scan.position.x);
+        write_system_state(indices.position[1], scan.position.y);
+        write_system_state(indices.velocity[0], scan.velocity.x);
+        write_system_state(indices.velocity[1], scan.velocity.y);
+        write_system_state(indices.rssi, scan.rssi);
+        write_system_state(indices.snr, scan.snr);
+    }
+
+    // Delivers whatever was sent on the selected radio this tick back to it,
+    // as if another ship had sent it. Sending and receiving share the same
+    // data slots, so this only has to raise the received flag.
+    pub fn loop_back_radio(&mut self) {
+        let sel = read_system_state(SystemState::SelectedRadio) as usize;
+        let indices = radio_internal::radio_indices(sel);
+        write_system_state(indices.send, 0.);
+        write_system_state(indices.receive, 1.);
+    }
+
+    // Moves the clock forward by n ticks and clears the commands issued
+    // during the previous tick. Our ship doesn't move: tests that need
+    // physics should set the ship state themselves.
+    pub fn advance_ticks(&mut self, n: u32) {
+        self.set_tick(current_tick() + n);
+        for command in COMMANDS {
+            write_system_state(command, 0.);
+        }
+        oort_api::dbg::reset();
+    }
+
+    // The angular acceleration commanded this tick.
+    pub fn torque(&self) -> f64 {
+        read_system_state(SystemState::Torque)
+    }
+
+    // The linear acceleration commanded this tick, in world coordinates and
+    // after the game's clamping to the ship's limits.
+    pub fn acceleration(&self) -> Vec2 {
+        vec2(
+            read_system_state(SystemState::AccelerateX),
+            read_system_state(SystemState::AccelerateY),
+        )
+        .rotate(heading())
+    }
+
+    // Whether the given gun was fired this tick.
+    pub fn fired(&self, index: usize) -> bool {
+        let state = match index {
+            0 => SystemState::Fire0,
+            1 => SystemState::Fire1,
+            2 => SystemState::Fire2,
+            3 => SystemState::Fire3,
+            _ => return false,
+        };
+        read_system_state(state) != 0.
+    }
+
+    // The heading the given turret was aimed at this tick.
+    pub fn aimed(&self, index: usize) -> f64 {
+        let state = match index {
+            0 => SystemState::Aim0,
+            1 => SystemState::Aim1,
+            2 => SystemState::Aim2,
+            3 => SystemState::Aim3,
+            _ => return 0.,
+        };
+        read_system_state(state)
+    }
+
+    pub fn exploded(&self) -> bool {
+        read_system_state(SystemState::Explode) != 0.
+    }
+}
+
+pub fn scan_result(class: Class, position: Vec2, velocity: Vec2) -> ScanResult {
+    ScanResult {
+        class,
+        position,
+        velocity,
+        rssi: 0.,
+        snr: 30.,
+    }
+}
@@ -1,12 +1,16 @@
 use crate::control::*;
-use oort_api::prelude::{maths_rs::{num::Base, *}, *};
+use oort_api::prelude::{maths_rs::num::Base, *};
 
 pub struct Ship {
     helm: Helm,
     v_prev: Vec2,
 }
 
-const BULLET_SPEED: f64 = 1000.0; // m/s
+impl Default for Ship {
+    fn default() -> Self {
+        Self::new()
+    }
+}
 
 impl Ship {
     pub fn new() -> Ship {
@@ -19,6 +23,6 @@ impl Ship {
     pub fn tick(&mut self) {
         let target_acceleration = (target_velocity() - self.v_prev) / TICK_LENGTH;
         self.v_prev = target_velocity();
-        turn_and_shoot_at(&mut self.helm, target(), target_velocity(), target_acceleration, 1000.0);
+        turn_and_shoot_at(&mut self.helm, target(), target_velocity(), target_acceleration, &FIGHTER_GUN);
     }
 }
\ No newline at end of file
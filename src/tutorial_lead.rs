@@ -1,11 +1,15 @@
 use crate::control::*;
-use oort_api::prelude::{maths_rs::{num::Base, *}, *};
+use oort_api::prelude::{maths_rs::num::Base, *};
 
 pub struct Ship {
     helm: Helm,
 }
 
-const BULLET_SPEED: f64 = 1000.0; // m/s
+impl Default for Ship {
+    fn default() -> Self {
+        Self::new()
+    }
+}
 
 impl Ship {
     pub fn new() -> Ship {
@@ -15,6 +19,6 @@ impl Ship {
     }
 
     pub fn tick(&mut self) {
-        turn_and_shoot_at(&mut self.helm, target(), target_velocity(), Vec2::zero(), 1000.0);
+        turn_and_shoot_at(&mut self.helm, target(), target_velocity(), Vec2::zero(), &FIGHTER_GUN);
     }
 }
\ No newline at end of file
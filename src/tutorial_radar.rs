@@ -1,18 +1,23 @@
 use crate::{
     contacts::*,
     control::*,
+    fire_control::*,
     radar::*,
 };
-use maths_rs::*;
 use oort_api::prelude::*;
 
 pub struct Ship {
     contacts: Contacts,
     radar: Radar,
     helm: Helm,
+    fire_control: FireControl,
 }
 
-const BULLET_SPEED: f64 = 1000.0; // m/s
+impl Default for Ship {
+    fn default() -> Self {
+        Self::new()
+    }
+}
 
 impl Ship {
     pub fn new() -> Ship {
@@ -20,6 +25,7 @@ impl Ship {
             contacts: Contacts::new(),
             radar: Radar::new(),
             helm: Helm::new(),
+            fire_control: FireControl::default(),
         }
     }
 
@@ -32,15 +38,8 @@ impl Ship {
         self.radar.tick(&mut self.contacts);
         self.contacts.draw();
 
-        let dist = |a: &Contact| (position() - a.pos()).length();
-        if let Some(contact) = self.contacts.iter_mut().min_by(|a, b| a.id.cmp(&b.id)) {
-            turn_and_shoot_at(
-                &mut self.helm,
-                contact.pos(),
-                contact.vel(),
-                contact.acc(),
-                BULLET_SPEED,
-            );
+        if let Some(contact) = self.contacts.confirmed().min_by_key(|c| c.id) {
+            self.fire_control.engage(&mut self.helm, contact, &FIGHTER_GUN);
         }
     }
 }
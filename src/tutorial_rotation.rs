@@ -9,12 +9,18 @@
 // The turn function takes a speed argument, where positive speeds result in turning left
 // and negative speeds will turn right.
 use crate::control::*;
-use oort_api::prelude::{maths_rs::{num::Base, *}, *};
+use oort_api::prelude::{maths_rs::num::Base, *};
 
 pub struct Ship {
     helm: Helm,
 }
 
+impl Default for Ship {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl Ship {
     pub fn new() -> Ship {
         Ship {
@@ -23,6 +29,6 @@ impl Ship {
     }
 
     pub fn tick(&mut self) {
-        turn_and_shoot_at(&mut self.helm, target(), Vec2::zero(), Vec2::zero(), 1000.0);
+        turn_and_shoot_at(&mut self.helm, target(), Vec2::zero(), Vec2::zero(), &FIGHTER_GUN);
     }
 }
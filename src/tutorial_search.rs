@@ -1,18 +1,27 @@
 use crate::{
     contacts::*,
     control::*,
+    fire_control::*,
+    missile::Launchers,
     radar::*,
+    weapons::*,
 };
-use maths_rs::*;
 use oort_api::prelude::*;
 
 pub struct Ship {
     contacts: Contacts,
     radar: Radar,
     helm: Helm,
+    fire_control: FireControl,
+    weapons: Weapons,
+    launchers: Launchers,
 }
 
-const BULLET_SPEED: f64 = 1000.0; // m/s
+impl Default for Ship {
+    fn default() -> Self {
+        Self::new()
+    }
+}
 
 impl Ship {
     pub fn new() -> Ship {
@@ -20,6 +29,9 @@ impl Ship {
             contacts: Contacts::new(),
             radar: Radar::new(),
             helm: Helm::new(),
+            fire_control: FireControl::default(),
+            weapons: Weapons::new(class()),
+            launchers: Launchers::new(class()),
         }
     }
 
@@ -32,15 +44,16 @@ impl Ship {
         self.radar.tick(&mut self.contacts);
         self.contacts.draw();
 
-        let dist = |a: &Contact| (position() - a.pos()).length();
-        if let Some(contact) = self.contacts.iter_mut().min_by(|a, b| a.id.cmp(&b.id)) {
-            turn_and_shoot_at(
-                &mut self.helm,
-                contact.pos(),
-                contact.vel(),
-                contact.acc(),
-                BULLET_SPEED,
-            );
+        // This is also the AI for frigates and cruisers outside of the
+        // tutorials, so use whatever guns we have: the hull points the
+        // forward gun, if there is one, and the turrets pick their own
+        // targets.
+        if let Some(contact) = self.contacts.confirmed().min_by_key(|c| c.id)
+            && let Some(gun) = main_gun(class())
+        {
+            self.fire_control.engage(&mut self.helm, contact, &gun);
         }
+        self.weapons.tick(&self.contacts, &self.fire_control);
+        self.launchers.tick(&self.contacts);
     }
 }
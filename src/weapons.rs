@@ -0,0 +1,236 @@
+// The guns on each class of ship, and control of the turrets among them.
+//
+// Fixed guns fire along the ship's heading, so aiming them means turning the
+// whole ship: see FireControl::engage. Turrets can be slewed independently
+// with aim(), which leaves the hull free to do something else. Each tick the
+// turrets are shared out among the confirmed contacts, each computes its own
+// lead from its own position on the ship, and fires when the shot is likely
+// to hit.
+//
+// The stats are approximations of the game's.
+use crate::{
+    assignment::hungarian,
+    contacts::{Contact, Contacts},
+    control::{FIGHTER_GUN, FRIGATE_MAIN_GUN, FiringSolution, Gun},
+    fire_control::FireControl,
+    radar::ship_dim,
+};
+use oort_api::prelude::*;
+
+// A gun and the index the game knows it by in aim() and fire().
+#[derive(Clone, Copy, Debug)]
+pub struct Weapon {
+    pub index: usize,
+    pub gun: Gun,
+}
+
+// A frigate has a turret on either side.
+pub const FRIGATE_TURRET: Gun = Gun {
+    speed: 1000.,
+    offset: Vec2 { x: 0., y: 15. },
+    lifetime: 5.,
+    turret: true,
+};
+
+// A cruiser's flak turret, in front of its missile launchers.
+pub const CRUISER_TURRET: Gun = Gun {
+    speed: 2000.,
+    offset: Vec2 { x: 100., y: 0. },
+    lifetime: 2.5,
+    turret: true,
+};
+
+// Every gun on a ship of the given class. Missile and torpedo launchers are
+// not guns and aren't included.
+pub fn guns(class: Class) -> Vec<Weapon> {
+    let weapon = |index, gun| Weapon { index, gun };
+    match class {
+        Class::Fighter => vec![weapon(0, FIGHTER_GUN)],
+        Class::Frigate => vec![
+            weapon(0, FRIGATE_MAIN_GUN),
+            weapon(1, FRIGATE_TURRET),
+            weapon(
+                2,
+                Gun {
+                    offset: Vec2 { x: 0., y: -15. },
+                    ..FRIGATE_TURRET
+                },
+            ),
+        ],
+        Class::Cruiser => vec![weapon(0, CRUISER_TURRET)],
+        _ => Vec::new(),
+    }
+}
+
+// How far the guns on a ship of the given class reach, ignoring the
+// velocity bullets inherit from the ship. Zero if it has no guns.
+pub fn gun_range(class: Class) -> f64 {
+    guns(class)
+        .iter()
+        .map(|w| w.gun.speed * w.gun.lifetime)
+        .fold(0., f64::max)
+}
+
+// Used in the assignment for turret/contact pairs that can't be engaged.
+const OUT_OF_RANGE_COST: f64 = 1e6;
+
+pub struct Weapons {
+    turrets: Vec<Weapon>,
+    // The id of the contact each turret is engaging, if any.
+    targets: Vec<Option<u32>>,
+}
+
+impl Weapons {
+    pub fn new(class: Class) -> Weapons {
+        let turrets: Vec<Weapon> = guns(class).into_iter().filter(|w| w.gun.turret).collect();
+        let targets = vec![None; turrets.len()];
+        Weapons { turrets, targets }
+    }
+
+    pub fn turrets(&self) -> &[Weapon] {
+        &self.turrets
+    }
+
+    // The id of the contact the i-th turret is engaging.
+    pub fn target(&self, i: usize) -> Option<u32> {
+        self.targets[i]
+    }
+
+    // Assigns the turrets to confirmed contacts, aims them and fires those
+    // that are on target. Returns the number of turrets fired.
+    pub fn tick(&mut self, contacts: &Contacts, fire_control: &FireControl) -> usize {
+        let contacts: Vec<&Contact> = contacts.confirmed().collect();
+        let solutions: Vec<Vec<Option<FiringSolution>>> = self
+            .turrets
+            .iter()
+            .map(|turret| {
+                contacts
+                    .iter()
+                    .map(|c| {
+                        FiringSolution::new(&turret.gun, c.pos(), c.vel(), c.acc())
+                            .filter(|s| s.in_range())
+                    })
+                    .collect()
+            })
+            .collect();
+
+        // Prefer the quickest shots, and spread the turrets over as many
+        // contacts as we can. Turrets left over once every contact has one
+        // double up on whichever contact they have the quickest shot at.
+        let costs: Vec<Vec<f64>> = solutions
+            .iter()
+            .map(|row| {
+                row.iter()
+                    .map(|s| s.map_or(OUT_OF_RANGE_COST, |s| s.time_of_flight))
+                    .collect()
+            })
+            .collect();
+        let assignment = hungarian(&costs);
+
+        let mut fired = 0;
+        for (i, turret) in self.turrets.iter().enumerate() {
+            let quickest = (0..contacts.len())
+                .filter(|&c| solutions[i][c].is_some())
+                .min_by(|&a, &b| costs[i][a].total_cmp(&costs[i][b]));
+            let chosen = assignment[i]
+                .filter(|&c| solutions[i][c].is_some())
+                .or(quickest);
+            self.targets[i] = chosen.map(|c| contacts[c].id);
+            let Some(c) = chosen else {
+                continue;
+            };
+            let (contact, solution) = (contacts[c], solutions[i][c].unwrap());
+
+            // Turrets are assumed to slew to their heading by the time the
+            // bullet is fired.
+            aim(turret.index, solution.heading);
+            let covariance = contact.pos_covariance_after(TICK_LENGTH + solution.time_of_flight);
+            let radius = ship_dim(contact.class()) / 2.;
+            if fire_control.should_fire(&solution, solution.heading, &covariance, radius) {
+                fire(turret.index);
+                fired += 1;
+            }
+        }
+        fired
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_env::*;
+
+    // Returns contacts with a confirmed track on a fighter at each position.
+    fn confirmed_contacts(env: &mut Env, positions: &[Vec2]) -> Contacts {
+        let mut contacts = Contacts::new();
+        let scans = || {
+            positions
+                .iter()
+                .map(|&p| scan_result(Class::Fighter, p, vec2(0., 0.)))
+        };
+        contacts.recv_contacts(scans().collect());
+        env.advance_ticks(1);
+        contacts.tick();
+        contacts.recv_contacts(scans().collect());
+        assert_eq!(contacts.confirmed().count(), positions.len());
+        contacts
+    }
+
+    #[test]
+    fn only_frigates_and_cruisers_have_turrets() {
+        assert!(Weapons::new(Class::Fighter).turrets().is_empty());
+        assert_eq!(Weapons::new(Class::Frigate).turrets().len(), 2);
+        assert_eq!(Weapons::new(Class::Cruiser).turrets().len(), 1);
+        assert!(guns(Class::Missile).is_empty());
+    }
+
+    #[test]
+    fn gun_range_is_longest_reach() {
+        assert_eq!(gun_range(Class::Fighter), 5000.);
+        // The main gun outranges the turrets.
+        assert_eq!(gun_range(Class::Frigate), 8000.);
+        assert_eq!(gun_range(Class::Cruiser), 5000.);
+        assert_eq!(gun_range(Class::Missile), 0.);
+    }
+
+    #[test]
+    fn turrets_engage_separate_contacts() {
+        let mut env = Env::new(Class::Frigate);
+        let contacts = confirmed_contacts(&mut env, &[vec2(1000., 500.), vec2(-800., -600.)]);
+        let mut weapons = Weapons::new(Class::Frigate);
+        let fired = weapons.tick(&contacts, &FireControl::default());
+
+        assert_ne!(weapons.target(0), None);
+        assert_ne!(weapons.target(1), None);
+        assert_ne!(weapons.target(0), weapons.target(1));
+        assert_eq!(fired, 2);
+        for (i, turret) in weapons.turrets().iter().enumerate() {
+            let contact = contacts.at(weapons.target(i).unwrap()).unwrap();
+            let bearing = (contact.pos() - position()).angle();
+            assert!(angle_diff(env.aimed(turret.index), bearing).abs() < 0.05);
+            assert!(env.fired(turret.index));
+        }
+        // The hull is left alone.
+        assert!(!env.fired(0));
+        assert_eq!(env.torque(), 0.);
+    }
+
+    #[test]
+    fn spare_turrets_double_up() {
+        let mut env = Env::new(Class::Frigate);
+        let contacts = confirmed_contacts(&mut env, &[vec2(1000., 0.)]);
+        let mut weapons = Weapons::new(Class::Frigate);
+        weapons.tick(&contacts, &FireControl::default());
+        assert_eq!(weapons.target(0), Some(0));
+        assert_eq!(weapons.target(1), Some(0));
+    }
+
+    #[test]
+    fn out_of_range_contacts_are_not_engaged() {
+        let mut env = Env::new(Class::Frigate);
+        let contacts = confirmed_contacts(&mut env, &[vec2(20000., 0.)]);
+        let mut weapons = Weapons::new(Class::Frigate);
+        assert_eq!(weapons.tick(&contacts, &FireControl::default()), 0);
+        assert_eq!(weapons.target(0), None);
+    }
+}